@@ -0,0 +1,108 @@
+// Accessibility tree for screen readers
+// Menu buttons, the dialogue line and the cards in the hand are exposed with labels,
+// the line is a live region and the selected card takes the focus so it is announced
+
+use super::dialogue::{DialogueState, DialogueText, DialogueCard};
+use bevy::{
+    prelude::*,
+    a11y::{AccessibilityNode, Focus, accesskit::{NodeBuilder, Role, Live, Action}},
+};
+
+// ---
+// Components
+
+// Text of the dialogue box, the line is drawn on a texture so it needs its own node
+#[derive(Component)]
+pub struct DialogueLine;
+
+// ---
+// Functions
+
+// Accessibility node with a role and a label
+pub fn label(role : Role, name : &str) -> AccessibilityNode {
+    let mut node = NodeBuilder::new(role);
+    node.set_name(name);
+    if role == Role::Button {
+        node.add_action(Action::Focus);
+        node.add_action(Action::Default);
+    }
+    AccessibilityNode::from(node)
+}
+
+// Region that is read again every time its label changes
+pub fn live(role : Role) -> AccessibilityNode {
+    let mut node = NodeBuilder::new(role);
+    node.set_live(Live::Polite);
+    AccessibilityNode::from(node)
+}
+
+// ---
+// Update systems
+
+// Hide the buttons of the screens that are not shown, so only the current ones are read
+// It runs when the state changes, the screens set their Visibility when entering and exiting it
+// but the computed one is only updated later in the frame, so the parents are checked instead
+pub fn menu_access_update(mut buttons : Query<(Entity, &mut AccessibilityNode), With<Button>>,
+                          nodes : Query<(&Visibility, Option<&Parent>)>) {
+    for (e, mut node) in buttons.iter_mut() {
+        let mut visible = true;
+        let mut current = Some(e);
+        while let Some((visibility, parent)) = current.and_then(|e| nodes.get(e).ok()) {
+            if *visibility == Visibility::Hidden {
+                visible = false;
+                break;
+            }
+            current = parent.map(|p| p.get());
+        }
+
+        if visible {
+            node.clear_hidden();
+        } else {
+            node.set_hidden();
+        }
+    }
+}
+
+// Announce every new dialogue line, the whole line at once instead of the characters as they are revealed
+pub fn line_access_update(text : Res<DialogueText>, mut line : Query<&mut AccessibilityNode, With<DialogueLine>>) {
+    if !text.is_changed() {
+        return;
+    }
+    let Ok(mut node) = line.get_single_mut() else { return; };
+    let name = match &text.voice {
+        Some(voice) => format!("{}: {}", voice, text.line()),
+        None => text.line().to_string(),
+    };
+    if node.name() != Some(name.as_str()) {
+        node.set_name(name);
+    }
+}
+
+// Label the cards in the hand with their words and move the focus with the selected card
+pub fn card_access_update(mut cmd : Commands,
+                          state : Res<DialogueState>,
+                          mut focus : ResMut<Focus>,
+                          mut cards : Query<(Entity, &DialogueCard, Option<&mut AccessibilityNode>)>,
+                          mut previous : Local<Option<Entity>>) {
+    if state.is_changed() {
+        for (e, card, node) in cards.iter_mut() {
+            let name = state.card_text(card.id()).unwrap_or_else(|| card.id().to_string());
+            match node {
+                Some(mut node) if node.name() != Some(name.as_str()) => node.set_name(name),
+                Some(_) => (),
+                None => {
+                    let mut node = label(Role::ListItem, &name);
+                    node.add_action(Action::Focus);
+                    cmd.entity(e).insert(node);
+                }
+            }
+        }
+    }
+
+    if state.selected_card != *previous {
+        *previous = state.selected_card;
+        if let Some(card) = state.selected_card {
+            **focus = Some(card);
+        }
+    }
+}
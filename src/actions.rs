@@ -0,0 +1,197 @@
+// Input abstraction, keyboard, mouse and gamepad are mapped to game actions that can be rebound
+
+use super::PersistentStorage;
+use bevy::{prelude::*, input::InputSystem};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+// ---
+// Constants
+
+const STICK_DEADZONE : f32 = 0.5;
+
+// ---
+// Plugin
+
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(InputMap::defaults())
+           .insert_resource(Actions::default())
+           .insert_resource(Rebinding::default())
+           .add_systems(Startup, load_bindings)
+           .add_systems(PreUpdate, update.after(InputSystem))
+           .add_systems(Update, (rebind_update, save_bindings.run_if(resource_changed::<InputMap>())));
+    }
+}
+
+// ---
+// Resources
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Action {
+    Left,
+    Right,
+    Confirm,
+    Cancel,
+    Continue,
+    Menu,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Pad(GamepadButtonType),
+    // Direction of the left stick, only triggers when crossing the deadzone
+    Stick(f32),
+}
+
+impl Binding {
+    fn name(&self) -> String {
+        match self {
+            Binding::Key(k) => format!("{:?}", k),
+            Binding::Mouse(m) => format!("Mouse {:?}", m),
+            Binding::Pad(p) => format!("Pad {:?}", p),
+            Binding::Stick(dir) => (if *dir < 0. { "Stick left" } else { "Stick right" }).to_string(),
+        }
+    }
+}
+
+// Bindings for each action, saved so they can be changed
+#[derive(Resource, Default, Serialize, Deserialize)]
+pub struct InputMap(HashMap<Action, Vec<Binding>>);
+
+impl InputMap {
+    // Bind an input to an action, the same input can trigger several actions
+    pub fn bind(mut self, action : Action, bindings : &[Binding]) -> Self {
+        self.0.entry(action).or_default().extend_from_slice(bindings);
+        self
+    }
+
+    // Replace the bindings of an action that use the same kind of input as the new one
+    pub fn rebind(&mut self, action : Action, binding : Binding) {
+        let bindings = self.0.entry(action).or_default();
+        bindings.retain(|b| std::mem::discriminant(b) != std::mem::discriminant(&binding));
+        bindings.push(binding);
+    }
+
+    // Keyboard and mouse bindings of an action, as shown in the settings
+    pub fn describe(&self, action : Action) -> String {
+        let names : Vec<String> = self.0.get(&action).into_iter().flatten()
+            .filter(|b| matches!(b, Binding::Key(_) | Binding::Mouse(_)))
+            .map(|b| b.name())
+            .collect();
+        if names.is_empty() { "None".to_string() } else { names.join(", ") }
+    }
+
+    pub fn defaults() -> Self {
+        use Binding::*;
+        InputMap::default()
+            .bind(Action::Left, &[Key(KeyCode::Left), Key(KeyCode::A), Pad(GamepadButtonType::DPadLeft), Stick(-1.)])
+            .bind(Action::Right, &[Key(KeyCode::Right), Key(KeyCode::D), Pad(GamepadButtonType::DPadRight), Stick(1.)])
+            .bind(Action::Confirm, &[Mouse(MouseButton::Left), Key(KeyCode::Return), Pad(GamepadButtonType::South)])
+            .bind(Action::Cancel, &[Mouse(MouseButton::Right), Key(KeyCode::Back), Pad(GamepadButtonType::East)])
+            .bind(Action::Continue, &[Key(KeyCode::Space), Key(KeyCode::Return), Pad(GamepadButtonType::South)])
+            .bind(Action::Menu, &[Key(KeyCode::Escape), Pad(GamepadButtonType::Start)])
+    }
+}
+
+// Actions pressed this frame
+#[derive(Resource, Default)]
+pub struct Actions {
+    pressed : HashSet<Action>,
+    pub using_mouse : bool,
+}
+
+impl Actions {
+    pub fn just_pressed(&self, action : Action) -> bool {
+        self.pressed.contains(&action)
+    }
+}
+
+// Action chosen in the settings to be rebound, the next key or button pressed replaces its binding
+#[derive(Resource, Default)]
+pub struct Rebinding(pub Option<Action>);
+
+// ---
+// Startup systems
+
+// Use the saved bindings over the defaults, actions missing in older saves keep their default inputs
+fn load_bindings(storage : Res<PersistentStorage>, mut map : ResMut<InputMap>) {
+    if let Ok(InputMap(saved)) = storage.0.get::<InputMap>("input_map") {
+        map.0.extend(saved);
+    }
+}
+
+// ---
+// Update systems
+
+fn save_bindings(mut storage : ResMut<PersistentStorage>, map : Res<InputMap>) {
+    if storage.0.set("input_map", &*map).is_err() {
+        println!("Warning, problem saving the input bindings");
+    }
+}
+
+// Bind the next key or button pressed to the action that is being rebound
+fn rebind_update(mut rebinding : ResMut<Rebinding>,
+                 mut map : ResMut<InputMap>,
+                 keyboard : Res<Input<KeyCode>>,
+                 mouse : Res<Input<MouseButton>>,
+                 gamepads : Res<Gamepads>,
+                 buttons : Res<Input<GamepadButton>>) {
+    // The click that started rebinding is not the new input
+    let Some(action) = rebinding.0 else { return; };
+    if rebinding.is_changed() {
+        return;
+    }
+
+    let binding = keyboard.get_just_pressed().next().map(|k| Binding::Key(*k))
+        .or_else(|| mouse.get_just_pressed().next().map(|m| Binding::Mouse(*m)))
+        .or_else(|| buttons.get_just_pressed().find(|b| gamepads.contains(b.gamepad)).map(|b| Binding::Pad(b.button_type)));
+    if let Some(binding) = binding {
+        map.rebind(action, binding);
+        rebinding.0 = None;
+    }
+}
+
+// Translate the raw input into actions
+pub fn update(map : Res<InputMap>,
+              mut actions : ResMut<Actions>,
+              keyboard : Res<Input<KeyCode>>,
+              mouse : Res<Input<MouseButton>>,
+              mut cursor : EventReader<CursorMoved>,
+              gamepads : Res<Gamepads>,
+              buttons : Res<Input<GamepadButton>>,
+              axes : Res<Axis<GamepadAxis>>,
+              mut stick_prev : Local<f32>) {
+    // Left stick, using the first connected gamepad that moves it
+    let stick = gamepads.iter()
+        .filter_map(|pad| axes.get(GamepadAxis::new(pad, GamepadAxisType::LeftStickX)))
+        .find(|x| x.abs() > STICK_DEADZONE)
+        .unwrap_or(0.);
+    let stick_pressed = |dir : f32| stick * dir > STICK_DEADZONE && *stick_prev * dir <= STICK_DEADZONE;
+
+    let mut pressed = HashSet::new();
+    for (action, bindings) in map.0.iter() {
+        let is_pressed = bindings.iter().any(|b| match b {
+            Binding::Key(k) => keyboard.just_pressed(*k),
+            Binding::Mouse(m) => mouse.just_pressed(*m),
+            Binding::Pad(p) => gamepads.iter().any(|pad| buttons.just_pressed(GamepadButton::new(pad, *p))),
+            Binding::Stick(dir) => stick_pressed(*dir),
+        });
+        if is_pressed {
+            pressed.insert(*action);
+        }
+    }
+    *stick_prev = stick;
+
+    // Remember the last device used, so the mouse hover doesn't override the keyboard or gamepad
+    if cursor.iter().last().is_some() || mouse.get_just_pressed().next().is_some() {
+        actions.using_mouse = true;
+    } else if !pressed.is_empty() {
+        actions.using_mouse = false;
+    }
+    actions.pressed = pressed;
+}
@@ -0,0 +1,295 @@
+// Music, sound effects, voices and ambient sounds
+// Every music track has a calm and a tense layer that crossfade with $anxiety,
+// and the script changes them with <<music track>> (or <<music stop>>) and <<sfx name>>
+
+use super::{AssetsLoading, CamId, settings::Settings, dialogue::{DialogueCommand, DialogueText, hash_obj}, yarn::YarnManager};
+use bevy::{
+    prelude::*,
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    utils::BoxedFuture,
+    reflect::TypeUuid
+};
+use serde::Deserialize;
+use yarn_spinner::YarnValue;
+use std::collections::HashMap;
+
+// ---
+// Constants
+
+// Anxiety at which only the tense layer is heard
+const ANXIETY_FULL : f32 = 5.;
+// Seconds to crossfade between the layers and between tracks
+const LAYER_FADE_TIME : f32 = 2.;
+const TRACK_FADE_TIME : f32 = 1.5;
+
+// Ambient emitters: node of the scene they are attached to, sound and position if the node is missing
+// Sounds come in pairs (<sound>_left.wav and <sound>_right.wav, each with one channel silent) that are mixed to pan them around the player
+const EMITTERS : [(&str, &str, Vec3); 3] = [
+    ("Radio", "radio", Vec3::new(1.57, 3.75, -3.39)),
+    ("Rain", "rain", Vec3::new(-12.0, 6.0, 8.0)),
+    ("Crowd", "crowd", Vec3::new(0.0, 4.0, 0.0)),
+];
+const AMBIENT_FADE_TIME : f32 = 3.;
+const AMBIENT_ROLLOFF : f32 = 0.08;
+
+// ---
+// Plugin
+
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<VoicesAsset>()
+           .init_asset_loader::<VoicesAssetLoader>()
+           .insert_resource(Music::default())
+           .insert_resource(Ambient::default())
+           .add_systems(Startup, voices_init)
+           .add_systems(Update, ((command_update, music_update).chain(), voice_update, ambient_update));
+    }
+}
+
+// ---
+// Resources
+
+struct Track {
+    name : String,
+    calm : Handle<AudioSink>,
+    tense : Handle<AudioSink>,
+    fade : f32,
+    playing : bool,
+}
+
+#[derive(Resource, Default)]
+pub struct Music {
+    tracks : Vec<Track>,
+    // 0 is only the calm layer and 1 only the tense one
+    mix : f32,
+}
+
+impl Music {
+    // Start a track, fading out the rest
+    fn play(&mut self, name : &str, assets : &AssetServer, audio : &Audio, sinks : &Assets<AudioSink>) {
+        self.tracks.iter_mut().for_each(|t| t.playing = t.name == name);
+        if self.tracks.iter().any(|t| t.name == name) {
+            return;
+        }
+
+        let layer = |l : &str| {
+            let source = assets.load(format!("audio/music/{}_{}.wav", name, l));
+            sinks.get_handle(audio.play_with_settings(source, PlaybackSettings::LOOP.with_volume(0.)))
+        };
+        let (calm, tense) = (layer("calm"), layer("tense"));
+        self.tracks.push(Track { name : name.to_string(), calm, tense, fade : 0., playing : true });
+    }
+
+    fn stop(&mut self) {
+        self.tracks.iter_mut().for_each(|t| t.playing = false);
+    }
+}
+
+struct Emitter {
+    sound : &'static str,
+    left : Handle<AudioSink>,
+    right : Handle<AudioSink>,
+    position : Vec3,
+    intensity : f32,
+}
+
+#[derive(Resource, Default)]
+pub struct Ambient {
+    emitters : Vec<Emitter>,
+}
+
+#[derive(Deserialize)]
+struct Voice {
+    sound : String,
+    pitch : (f32, f32),
+    // Characters between blips
+    every : usize,
+}
+
+#[derive(Resource)]
+struct Voices(Handle<VoicesAsset>);
+
+// ---
+// Assets
+
+#[derive(TypeUuid, Deserialize)]
+#[uuid = "8f3e2d71-5c4a-4b6e-9a0d-2e7c1b5f9d43"]
+struct VoicesAsset {
+    voices : HashMap<String, Voice>,
+    emotions : HashMap<String, f32>,
+}
+
+#[derive(Default)]
+struct VoicesAssetLoader;
+
+impl AssetLoader for VoicesAssetLoader {
+    fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let voices = ron::de::from_bytes::<VoicesAsset>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(voices));
+            Ok(())
+        })
+    }
+    fn extensions(&self) -> &[&str] { &["voices.ron"] }
+}
+
+// ---
+// Startup systems
+
+fn voices_init(mut cmd : Commands, assets : Res<AssetServer>, mut loading : ResMut<AssetsLoading>) {
+    let voices = assets.load("dialogue/voices.ron");
+    loading.0.push(voices.clone_untyped());
+    cmd.insert_resource(Voices(voices));
+}
+
+// ---
+// Update systems
+
+// Music and sound effect commands from the script
+fn command_update(mut music : ResMut<Music>,
+                  mut commands : EventReader<DialogueCommand>,
+                  settings : Res<Settings>,
+                  assets : Res<AssetServer>,
+                  audio : Res<Audio>,
+                  sinks : Res<Assets<AudioSink>>) {
+    for DialogueCommand(c) in commands.iter() {
+        match (c[0].as_str(), c.get(1)) {
+            ("music", Some(track)) if track == "stop" => music.stop(),
+            ("music", Some(track)) => music.play(track, &assets, &audio, &sinks),
+            ("sfx", Some(name)) => {
+                let source = assets.load(format!("audio/sfx/{}.wav", name));
+                audio.play_with_settings(source, PlaybackSettings::ONCE.with_volume(settings.sfx()));
+            },
+            ("music", None) | ("sfx", None) => println!("Warning, missing the name in the {} command", c[0]),
+            _ => ()
+        }
+    }
+}
+
+// Crossfade the layers with the anxiety and the tracks when they change
+fn music_update(time : Res<Time>,
+                settings : Res<Settings>,
+                yarn : Res<YarnManager>,
+                mut music : ResMut<Music>,
+                sinks : Res<Assets<AudioSink>>) {
+    let anxiety = match yarn.storage.get("$anxiety") {
+        Some(YarnValue::F32(anxiety)) => *anxiety,
+        _ => 0.
+    };
+    let target = (anxiety / ANXIETY_FULL).clamp(0., 1.);
+    let step = time.delta_seconds() / LAYER_FADE_TIME;
+    music.mix += (target - music.mix).clamp(-step, step);
+
+    let mix = music.mix;
+    let step = time.delta_seconds() / TRACK_FADE_TIME;
+    for track in music.tracks.iter_mut() {
+        track.fade = (track.fade + if track.playing { step } else { -step }).clamp(0., 1.);
+
+        let volume = settings.music() * track.fade;
+        if let Some(sink) = sinks.get(&track.calm) { sink.set_volume(volume * (1. - mix)); }
+        if let Some(sink) = sinks.get(&track.tense) { sink.set_volume(volume * mix); }
+    }
+
+    // Remove the tracks that faded out
+    music.tracks.retain(|t| {
+        let done = !t.playing && t.fade <= 0.;
+        if done {
+            [&t.calm, &t.tense].iter().filter_map(|s| sinks.get(s)).for_each(|s| s.stop());
+        }
+        !done
+    });
+}
+
+// Play the voice of the speaker while the line is revealed, skipped lines are silent
+fn voice_update(text : Res<DialogueText>,
+                settings : Res<Settings>,
+                voices : Res<Voices>,
+                definitions : Res<Assets<VoicesAsset>>,
+                assets : Res<AssetServer>,
+                audio : Res<Audio>,
+                mut last : Local<usize>) {
+    let revealed = text.revealed();
+    if text.is_changed() && revealed < *last {
+        *last = 0;
+    }
+    if revealed <= *last {
+        return;
+    }
+    let previous = std::mem::replace(&mut *last, revealed);
+
+    let Some(definitions) = definitions.get(&voices.0) else { return; };
+    let Some(voice) = text.voice.as_ref().and_then(|v| definitions.voices.get(v)) else { return; };
+    if text.was_skipped() || voice.every == 0 || revealed / voice.every == previous / voice.every {
+        return;
+    }
+    match text.line().chars().nth(revealed - 1) {
+        Some(c) if !c.is_whitespace() => (),
+        _ => return
+    }
+
+    // Pseudo random pitch so replays sound the same
+    let t = (hash_obj((text.line(), revealed)) % 1000) as f32 / 1000.;
+    let emotion = text.emotion.as_ref().and_then(|e| definitions.emotions.get(e)).copied().unwrap_or(1.);
+    let pitch = (voice.pitch.0 + (voice.pitch.1 - voice.pitch.0) * t) * emotion;
+
+    audio.play_with_settings(assets.load(voice.sound.as_str()), PlaybackSettings::ONCE.with_volume(settings.sfx()).with_speed(pitch));
+}
+
+// How loud each ambient sound should be with the current story
+fn ambient_intensity(sound : &str, yarn : &YarnManager) -> f32 {
+    match sound {
+        "radio" => 0.5,
+        "rain" => match yarn.storage.get("$weather") {
+            Some(YarnValue::Str(w)) if w == "rainy" => 1.,
+            _ => 0.
+        },
+        "crowd" => match yarn.storage.get("$busy_work") {
+            Some(YarnValue::Bool(true)) => 1.,
+            _ => 0.2
+        },
+        _ => 0.
+    }
+}
+
+// Ambient sounds of the bar, panned around the player camera and silent outside of the game
+fn ambient_update(time : Res<Time>,
+                  settings : Res<Settings>,
+                  yarn : Res<YarnManager>,
+                  assets : Res<AssetServer>,
+                  audio : Res<Audio>,
+                  sinks : Res<Assets<AudioSink>>,
+                  mut ambient : ResMut<Ambient>,
+                  nodes : Query<(&Name, &GlobalTransform)>,
+                  cameras : Query<(&Camera, &GlobalTransform, &CamId)>) {
+    let Some((camera, listener, _)) = cameras.iter().find(|(_, _, id)| **id == CamId::Player) else { return; };
+
+    // Start the emitters the first time the game is shown, once the scene is spawned
+    if ambient.emitters.is_empty() {
+        if !camera.is_active { return; }
+        for (node, sound, fallback) in EMITTERS {
+            let position = nodes.iter().find(|(n, _)| n.as_str() == node).map_or(fallback, |(_, t)| t.translation());
+            let side = |s : &str| {
+                let source = assets.load(format!("audio/ambient/{}_{}.wav", sound, s));
+                sinks.get_handle(audio.play_with_settings(source, PlaybackSettings::LOOP.with_volume(0.)))
+            };
+            let (left, right) = (side("left"), side("right"));
+            ambient.emitters.push(Emitter { sound, left, right, position, intensity : 0. });
+        }
+    }
+
+    let step = time.delta_seconds() / AMBIENT_FADE_TIME;
+    for emitter in ambient.emitters.iter_mut() {
+        let target = if camera.is_active { ambient_intensity(emitter.sound, &yarn) } else { 0. };
+        emitter.intensity += (target - emitter.intensity).clamp(-step, step);
+
+        // Equal power panning with the direction in camera space
+        let local = listener.affine().inverse().transform_point3(emitter.position);
+        let pan = if local.length() > 0. { (local.x / local.length()).clamp(-1., 1.) } else { 0. };
+        let volume = settings.sfx() * emitter.intensity / (1. + local.length() * AMBIENT_ROLLOFF);
+
+        if let Some(sink) = sinks.get(&emitter.left) { sink.set_volume(volume * ((1. - pan) / 2.).sqrt()); }
+        if let Some(sink) = sinks.get(&emitter.right) { sink.set_volume(volume * ((1. + pan) / 2.).sqrt()); }
+    }
+}
@@ -0,0 +1,223 @@
+// Checkpoints reached during the story and the chapter select on the main menu
+// A checkpoint is marked in the script with <<checkpoint Node Title>>, at the start of the node it resumes from
+
+use super::{GameState, Props, StoryState, PersistentStorage, accessibility, dialogue::{DialogueState, CardStatus, WordType}, menu::{MenuButton, spawn_button, text_style, MENU_BUTTON_REGULAR, MENU_BUTTON_HOVER}, yarn::*};
+use bevy::{prelude::*, a11y::accesskit::Role};
+use serde::{Deserialize, Serialize};
+use yarn_spinner::{YarnStorage, YarnValue};
+use std::collections::HashMap;
+
+// ---
+// Constants
+
+const CHAPTERS_BACKGROUND : Color = Color::rgb(0.05, 0.12, 0.08);
+
+// ---
+// Resources
+
+// Yarn values that can be saved
+#[derive(Clone, Serialize, Deserialize)]
+enum SavedValue {
+    Number(f32),
+    Text(String),
+    Bool(bool),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub node : String,
+    pub title : String,
+    variables : HashMap<String, SavedValue>,
+    // Key, if it was played and words of every card, starting with the hand in the order it was dealt
+    cards : Vec<(String, bool, Vec<WordType>)>,
+    // Cards waiting in the deck, missing in older saves
+    #[serde(default)]
+    deck : Vec<String>,
+    is_marco_here : bool,
+    is_remie_here : bool,
+}
+
+impl Checkpoint {
+    pub fn new(node : &str, title : &str, storage : &YarnStorage, state : &DialogueState, story : &StoryState) -> Checkpoint {
+        let variables = storage.iter().map(|(k, v)| {
+            let v = match v {
+                YarnValue::F32(n) => SavedValue::Number(*n),
+                YarnValue::Str(s) => SavedValue::Text(s.clone()),
+                YarnValue::Bool(b) => SavedValue::Bool(*b),
+            };
+            (k.clone(), v)
+        }).collect();
+
+        let mut cards : Vec<(String, bool, Vec<WordType>)> = state.cards.iter()
+            .map(|(k, (status, words))| (k.clone(), matches!(status, CardStatus::Played), words.clone()))
+            .collect();
+        cards.sort_by_key(|(k, _, _)| state.hand.iter().position(|h| h == k).unwrap_or(usize::MAX));
+
+        Checkpoint {
+            node : node.to_string(),
+            title : if title.is_empty() { node.to_string() } else { title.to_string() },
+            variables,
+            cards,
+            deck : state.deck.clone(),
+            is_marco_here : story.is_marco_here,
+            is_remie_here : story.is_remie_here,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Checkpoints {
+    pub reached : Vec<Checkpoint>,
+    // Checkpoint to start from the next time the game is played
+    pub selected : Option<usize>,
+}
+
+impl Checkpoints {
+    // Save a checkpoint, replacing the previous one for the same node
+    pub fn reach(&mut self, checkpoint : Checkpoint) {
+        match self.reached.iter_mut().find(|c| c.node == checkpoint.node) {
+            Some(c) => *c = checkpoint,
+            None => self.reached.push(checkpoint),
+        }
+    }
+}
+
+// ---
+// Components
+
+#[derive(Component)]
+pub struct ChaptersNode;
+
+#[derive(Component)]
+pub struct ChapterButton(usize);
+
+// ---
+// Startup systems
+
+pub fn load_checkpoints(storage : Res<PersistentStorage>, mut checkpoints : ResMut<Checkpoints>) {
+    if let Ok(reached) = storage.0.get::<Vec<Checkpoint>>("checkpoints") {
+        checkpoints.reached = reached;
+    }
+}
+
+// ---
+// State transitions
+
+// Build the chapter select with the checkpoints reached so far
+pub fn enter_chapters(mut cmd : Commands, props : Res<Props>, checkpoints : Res<Checkpoints>) {
+    cmd.spawn((
+        NodeBundle {
+            style : Style {
+                size : Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                align_items : AlignItems::Center,
+                justify_content : JustifyContent::Center,
+                flex_direction: FlexDirection::Column,
+                gap : Size::new(Val::Auto, Val::Px(16.0)),
+                ..default()
+            },
+            background_color : CHAPTERS_BACKGROUND.into(),
+            ..default()
+        },
+        ChaptersNode
+    ))
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section("Chapters", text_style(&props, 48.0)));
+
+        if checkpoints.reached.is_empty() {
+            parent.spawn(TextBundle::from_section("No chapters reached yet", text_style(&props, 16.0)));
+        }
+
+        for (i, checkpoint) in checkpoints.reached.iter().enumerate() {
+            parent.spawn((
+                ButtonBundle {
+                    style : Style {
+                        size : Size::new(Val::Px(400.0), Val::Px(48.0)),
+                        justify_content : JustifyContent::Center,
+                        align_items : AlignItems::Center,
+                        ..default()
+                    },
+                    background_color : MENU_BUTTON_REGULAR.into(),
+                    ..default()
+                },
+                ChapterButton(i),
+                accessibility::label(Role::Button, &checkpoint.title)
+            )).with_children(|button| {
+                button.spawn(TextBundle::from_section(checkpoint.title.as_str(), text_style(&props, 20.0)));
+            });
+        }
+
+        spawn_button(parent, "Back", text_style(&props, 24.0), MenuButton::Back);
+    });
+}
+
+pub fn exit_chapters(mut cmd : Commands, node : Query<Entity, With<ChaptersNode>>) {
+    node.iter().for_each(|x| cmd.entity(x).despawn_recursive());
+}
+
+// Resume from the selected checkpoint, after the restart has reset the story
+pub fn restore_checkpoint(mut checkpoints : ResMut<Checkpoints>,
+                          mut story : ResMut<StoryState>,
+                          mut state : ResMut<DialogueState>,
+                          mut yarn : ResMut<YarnManager>,
+                          asset_lines : Res<Assets<YarnLinesAsset>>,
+                          mut asset_runner : ResMut<Assets<YarnRunnerAsset>>) {
+    let Some(checkpoint) = checkpoints.selected.take().and_then(|i| checkpoints.reached.get(i).cloned()) else { return; };
+
+    let (runner, _) = match get_yarn_components(&yarn, &mut asset_runner, &asset_lines) {
+        None => return,
+        Some(v) => v
+    };
+    if runner.set_node(&checkpoint.node).is_err() {
+        println!("Warning, the checkpoint node {} doesn't exist", checkpoint.node);
+        return;
+    }
+
+    yarn.storage = checkpoint.variables.into_iter().map(|(k, v)| {
+        let v = match v {
+            SavedValue::Number(n) => YarnValue::F32(n),
+            SavedValue::Text(s) => YarnValue::Str(s),
+            SavedValue::Bool(b) => YarnValue::Bool(b),
+        };
+        (k, v)
+    }).collect();
+
+    state.hand = checkpoint.cards.iter()
+        .filter(|(k, played, _)| !played && !checkpoint.deck.contains(k))
+        .map(|(k, _, _)| k.clone())
+        .collect();
+    state.cards = checkpoint.cards.into_iter()
+        .map(|(k, played, words)| {
+            let status = if played { CardStatus::Played } else if checkpoint.deck.contains(&k) { CardStatus::Deck } else { CardStatus::New(None) };
+            (k, (status, words))
+        })
+        .collect();
+    state.deck = checkpoint.deck;
+
+    story.is_marco_here = checkpoint.is_marco_here;
+    story.is_remie_here = checkpoint.is_remie_here;
+}
+
+// ---
+// Update systems
+
+pub fn save_checkpoints(mut storage : ResMut<PersistentStorage>, checkpoints : Res<Checkpoints>) {
+    if storage.0.set("checkpoints", &checkpoints.reached).is_err() {
+        println!("Warning, problem saving the checkpoints");
+    }
+}
+
+// Start the game from a chapter
+pub fn chapters_update(mut state : ResMut<NextState<GameState>>,
+                       mut checkpoints : ResMut<Checkpoints>,
+                       mut buttons : Query<(&Interaction, &mut BackgroundColor, &ChapterButton), Changed<Interaction>>) {
+    for (inter, mut bg, ChapterButton(i)) in buttons.iter_mut() {
+        match *inter {
+            Interaction::Clicked => {
+                checkpoints.selected = Some(*i);
+                state.set(GameState::Restart);
+            },
+            Interaction::Hovered => *bg = MENU_BUTTON_HOVER.into(),
+            Interaction::None => *bg = MENU_BUTTON_REGULAR.into(),
+        }
+    }
+}
@@ -0,0 +1,206 @@
+// Content warnings and the screen to hide topics
+// A node is tagged with a topic by adding cw:topic to the tags: of its header, hiding the whole node
+// When the topic is hidden the story jumps to <Node>Soft if it exists, or skips the lines with a summary,
+// and either way a notice tells the player what was hidden
+
+use super::{Props, AssetsLoading, accessibility, settings::Settings, menu::{MenuButton, spawn_button, text_style, MENU_BUTTON_REGULAR, MENU_BUTTON_HOVER}, yarn::YarnMetadataAsset};
+use bevy::{
+    prelude::*,
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    utils::BoxedFuture,
+    reflect::TypeUuid,
+    a11y::accesskit::Role
+};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+// ---
+// Constants
+
+const CONTENT_BACKGROUND : Color = Color::rgb(0.05, 0.12, 0.08);
+
+// ---
+// Plugin
+
+pub struct ContentPlugin;
+
+impl Plugin for ContentPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<TopicsAsset>()
+           .init_asset_loader::<TopicsAssetLoader>()
+           .add_systems(Startup, topics_init);
+    }
+}
+
+// ---
+// Resources
+
+#[derive(Deserialize)]
+pub struct Topic {
+    pub id : String,
+    pub name : String,
+    // Shown instead of the lines when the topic is hidden and there is no softened node
+    pub summary : String,
+}
+
+// Handle to the topic definitions
+#[derive(Resource)]
+pub struct Topics(pub Handle<TopicsAsset>);
+
+// ---
+// Assets
+
+#[derive(TypeUuid, Deserialize)]
+#[uuid = "2e9a4c17-6b3d-4f5e-b8a1-7c0d9e3f2b54"]
+pub struct TopicsAsset(pub Vec<Topic>);
+
+impl TopicsAsset {
+    // First topic of a node that the player chose to hide
+    pub fn hidden<'a>(&'a self, node : &str, metadata : &YarnMetadataAsset, hidden : &HashSet<String>) -> Option<&'a Topic> {
+        let id = hidden_topic(metadata.node_tags(node), hidden)?;
+        self.0.iter().find(|t| t.id == id)
+    }
+}
+
+#[derive(Default)]
+struct TopicsAssetLoader;
+
+impl AssetLoader for TopicsAssetLoader {
+    fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let topics = ron::de::from_bytes::<TopicsAsset>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(topics));
+            Ok(())
+        })
+    }
+    fn extensions(&self) -> &[&str] { &["topics.ron"] }
+}
+
+// ---
+// Components
+
+#[derive(Component)]
+pub struct ContentNode;
+
+#[derive(Component)]
+pub struct TopicButton(String);
+
+#[derive(Component)]
+pub struct TopicValue(String);
+
+// ---
+// Functions
+
+// First hidden topic in the tags of a node, also used by the headless replays that don't have the definitions
+pub fn hidden_topic<'a>(tags : &'a [String], hidden : &HashSet<String>) -> Option<&'a str> {
+    tags.iter()
+        .filter_map(|tag| tag.strip_prefix("cw:"))
+        .find(|id| hidden.contains(*id))
+}
+
+// ---
+// Startup systems
+
+fn topics_init(mut cmd : Commands, assets : Res<AssetServer>, mut loading : ResMut<AssetsLoading>) {
+    let topics = assets.load("dialogue/dialogue.topics.ron");
+    loading.0.push(topics.clone_untyped());
+    cmd.insert_resource(Topics(topics));
+}
+
+// ---
+// State transitions
+
+// Build the content screen with a toggle for every topic
+pub fn enter_content(mut cmd : Commands,
+                     props : Res<Props>,
+                     settings : Res<Settings>,
+                     topics : Res<Topics>,
+                     definitions : Res<Assets<TopicsAsset>>) {
+    let Some(TopicsAsset(definitions)) = definitions.get(&topics.0) else {
+        println!("Warning, the content topics are not loaded");
+        return;
+    };
+
+    cmd.spawn((
+        NodeBundle {
+            style : Style {
+                size : Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                align_items : AlignItems::Center,
+                justify_content : JustifyContent::Center,
+                flex_direction: FlexDirection::Column,
+                gap : Size::new(Val::Auto, Val::Px(16.0)),
+                ..default()
+            },
+            background_color : CONTENT_BACKGROUND.into(),
+            ..default()
+        },
+        ContentNode
+    ))
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section("Content", text_style(&props, 48.0)));
+        parent.spawn(TextBundle::from_section("Hidden topics are softened or summarized", text_style(&props, 16.0)));
+
+        for topic in definitions.iter() {
+            parent.spawn((
+                ButtonBundle {
+                    style : Style {
+                        size : Size::new(Val::Px(520.0), Val::Px(48.0)),
+                        justify_content : JustifyContent::Center,
+                        align_items : AlignItems::Center,
+                        ..default()
+                    },
+                    background_color : MENU_BUTTON_REGULAR.into(),
+                    ..default()
+                },
+                TopicButton(topic.id.clone()),
+                accessibility::label(Role::Button, &topic.name)
+            )).with_children(|button| {
+                button.spawn((
+                    TextBundle::from_section(topic_text(topic, &settings), text_style(&props, 20.0)),
+                    TopicValue(topic.id.clone())
+                ));
+            });
+        }
+
+        spawn_button(parent, "Back", text_style(&props, 24.0), MenuButton::Back);
+    });
+}
+
+pub fn exit_content(mut cmd : Commands, node : Query<Entity, With<ContentNode>>) {
+    node.iter().for_each(|x| cmd.entity(x).despawn_recursive());
+}
+
+// ---
+// Update systems
+
+fn topic_text(topic : &Topic, settings : &Settings) -> String {
+    let shown = if settings.hidden_topics.contains(&topic.id) { "Hidden" } else { "Shown" };
+    format!("{}: {}", topic.name, shown)
+}
+
+// Show or hide a topic
+pub fn content_update(mut settings : ResMut<Settings>,
+                      topics : Res<Topics>,
+                      definitions : Res<Assets<TopicsAsset>>,
+                      mut buttons : Query<(&Interaction, &mut BackgroundColor, &TopicButton), Changed<Interaction>>,
+                      mut values : Query<(&mut Text, &TopicValue)>) {
+    for (inter, mut bg, TopicButton(id)) in buttons.iter_mut() {
+        match *inter {
+            Interaction::Clicked => {
+                if !settings.hidden_topics.remove(id) {
+                    settings.hidden_topics.insert(id.clone());
+                }
+            },
+            Interaction::Hovered => *bg = MENU_BUTTON_HOVER.into(),
+            Interaction::None => *bg = MENU_BUTTON_REGULAR.into(),
+        }
+    }
+
+    if !settings.is_changed() { return; }
+    let Some(TopicsAsset(definitions)) = definitions.get(&topics.0) else { return; };
+    for (mut text, TopicValue(id)) in values.iter_mut() {
+        if let Some(topic) = definitions.iter().find(|t| t.id == *id) {
+            text.sections[0].value = topic_text(topic, &settings);
+        }
+    }
+}
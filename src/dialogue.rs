@@ -1,7 +1,9 @@
 // Dialogue system using the yarn spinner plugin for bevy
 
-use super::{Player, Props, AssetsLoading, StoryState, PersistentStorage, smoothstep, yarn::*, NUM_ENDINGS};
-use std::{collections::{HashMap, hash_map::DefaultHasher}, cmp::Ordering, hash::{Hash, Hasher}, fmt::{Formatter, Debug}};
+use super::{GameState, Player, Props, AssetsLoading, StoryState, PersistentStorage, smoothstep, accessibility, yarn::*, replay::Replay, actions::{Actions, Action}, settings::Settings, content::{Topics, TopicsAsset}, endings::{Endings, EndingsAsset}, checkpoints::{Checkpoint, Checkpoints}};
+use serde::{Deserialize, Serialize};
+use yarn_spinner::{YarnRunner, YarnStorage, YarnValue};
+use std::{collections::{HashMap, HashSet, hash_map::DefaultHasher}, cmp::Ordering, hash::{Hash, Hasher}, fmt::{Formatter, Debug}};
 use bevy::{
     prelude::*,
     core_pipeline::clear_color::ClearColorConfig,
@@ -10,16 +12,21 @@ use bevy::{
         render_resource::{Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages},
         view::RenderLayers, camera::RenderTarget
     }, 
+    a11y::accesskit::Role,
 };
 
 // ---
 // Constants
 
 const CARD_PADDING : f32 = 0.18;
+const IMPORTANT_PADDING : f32 = 0.3;
 const CARD_TEX_SIZE : Extent3d = Extent3d { width: 256, height: 320, depth_or_array_layers: 1 };
 const CARD_MESH_SIZE : Vec2 = Vec2::new(0.2, 0.25);
 const CARD_LERP_TIME : f32 = 0.2;
 const CARD_FONT_SIZE : f32 = 24.;
+const CARD_FIRST_LAYER : u8 = 3; // 1 is the menu and 2 the dialogue box
+// The hand holds at most this many cards, the extra ones that don't answer the current question go back to the deck
+const MAX_HAND : usize = 12;
 
 const DIALOGUE_TEX_SIZE : Extent3d = Extent3d { width: 768, height: 192, depth_or_array_layers: 1 };
 const DIALOGUE_MESH_SIZE : Vec2 = Vec2::new(6.0, 1.5);
@@ -35,6 +42,7 @@ const SPEAKER_WAITER : Color = Color::rgb(1.0, 0.7, 0.6);
 pub enum CardStatus {
     New(Option<usize>),
     Card(Entity, Option<usize>),
+    Deck,
     Played,
 }
 
@@ -44,6 +52,7 @@ impl Default for CardStatus {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub enum WordType {
     Regular(String),
     Varying(String),
@@ -71,8 +80,343 @@ pub struct DialogueState {
     pub selected_card : Option<Entity>,
     pub previous_card : Option<Entity>,
     pub cards : HashMap<String, (CardStatus, Vec<WordType>)>,
-    pub important_decision : [Option<String> ; 2],
-    pub important_id : [Option<Entity>; 2]
+    // Keys of the cards in the hand, in the order they were dealt, and of the ones waiting to be drawn
+    pub hand : Vec<String>,
+    pub deck : Vec<String>,
+    // Cards sent back to the deck, despawned by create_cards_update
+    pub returned : Vec<Entity>,
+    // Keys of the cards of an important decision, the only ones shown until it is answered
+    pub important_decision : Vec<String>,
+    // Option selected by cards that don't answer the current question, if it has one
+    pub other_option : Option<usize>,
+    // Elapsed and total time of the current wait command
+    pub wait_timer : (f32, f32),
+    // Node with a hidden topic whose lines are being skipped, and if its summary was shown
+    pub skipped_node : Option<(String, bool)>,
+    // Card that was just played, until what it caused is remembered
+    pub played : Option<PlayedCard>,
+    // Seconds left to answer a timed question and the card played when they run out (see timeout_card)
+    pub choice_timer : Option<(f32, Option<String>)>,
+}
+
+// What the story functions remember from previous runs, only in New Game+
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct RunMemory {
+    pub endings : HashSet<String>,
+    pub chosen : HashSet<String>,
+}
+
+// What happens to a line when its node has a hidden topic
+#[derive(PartialEq)]
+pub enum HiddenLine {
+    // Lines of nodes that are not hidden, and questions, which are still asked so the story can go on
+    Shown,
+    // The story jumped to <Node>Soft instead
+    Softened,
+    // First line of a skipped node, the summary is shown instead
+    Summary,
+    // The rest of the lines of a skipped node
+    Skipped,
+}
+
+// Question and card played, with the variables from before playing it
+pub struct PlayedCard {
+    question : u64,
+    card : String,
+    variables : Vec<(String, String)>,
+}
+
+impl DialogueState {
+    // Add the options of a question to the hand, options starting with + are only dealt in New Game+
+    // Returns if it is an important decision
+    pub fn add_options(&mut self, options : &[String], previous : &[String], new_game_plus : bool) -> bool {
+        for (_, (t, _)) in self.cards.iter_mut() {
+            match t {
+                CardStatus::New(opt) => *opt = None,
+                CardStatus::Card(_, opt) => *opt = None,
+                _ => ()
+            }
+        }
+
+        let mut important = false;
+        self.other_option = None;
+
+        for (opt_num, line) in options.iter().enumerate() {
+            for l in line.split('|') {
+                let l = l.trim();
+                if l == "other" {
+                    self.other_option = Some(opt_num);
+                    continue;
+                }
+                let l = match l.strip_prefix('+') {
+                    Some(l) if new_game_plus => l.trim(),
+                    Some(_) => continue,
+                    None => l
+                };
+
+                let key = option_key(l);
+                let prev_sel = previous.contains(&key);
+
+                if l.starts_with('!') {
+                    // Only these cards are shown, hide the others
+                    if !self.important_decision.contains(&key) {
+                        self.important_decision.push(key.to_string());
+                    }
+                    important = true;
+                }
+
+                self.deal(&key, option_words(l, prev_sel), Some(opt_num));
+            }
+        }
+        self.trim_hand();
+
+        important
+    }
+
+    // Put a card in the hand, taking it from the deck if it was there. Played cards stay played
+    fn deal(&mut self, key : &str, words : Vec<WordType>, opt : Option<usize>) {
+        let (st, w) = self.cards.entry(key.to_string()).or_insert((CardStatus::Deck, vec![]));
+        *w = words;
+        match st {
+            CardStatus::New(o) | CardStatus::Card(_, o) => *o = opt,
+            CardStatus::Deck => {
+                *st = CardStatus::New(opt);
+                self.deck.retain(|k| k != key);
+                self.hand.push(key.to_string());
+            },
+            CardStatus::Played => ()
+        }
+    }
+
+    // Send the oldest cards that don't answer the question back to the deck until the hand fits
+    fn trim_hand(&mut self) {
+        while self.hand.len() > MAX_HAND {
+            let Some(i) = self.hand.iter().position(|k| matches!(self.cards.get(k), Some((CardStatus::New(None) | CardStatus::Card(_, None), _)))) else { break; };
+            let key = self.hand.remove(i);
+            if let Some((st, _)) = self.cards.get_mut(&key) {
+                if let CardStatus::Card(e, _) = *st {
+                    self.returned.push(e);
+                }
+                *st = CardStatus::Deck;
+            }
+            self.deck.push(key);
+        }
+    }
+
+    // Fill the hand with the cards waiting in the deck
+    fn draw(&mut self) {
+        while self.hand.len() < MAX_HAND && !self.deck.is_empty() {
+            let key = self.deck.remove(0);
+            if let Some((st, _)) = self.cards.get_mut(&key) {
+                *st = CardStatus::New(None);
+            }
+            self.hand.push(key);
+        }
+    }
+
+    // Give a card from the script, even if it was played before
+    // A card already in the hand keeps answering the question it answered
+    pub fn give(&mut self, option : &str) {
+        let key = option_key(option);
+        let mut opt = None;
+        if let Some((st, _)) = self.cards.get_mut(&key) {
+            match st {
+                CardStatus::Played => *st = CardStatus::Deck,
+                CardStatus::New(o) | CardStatus::Card(_, o) => opt = *o,
+                CardStatus::Deck => ()
+            }
+        }
+        self.deal(&key, option_words(option, false), opt);
+        self.trim_hand();
+    }
+
+    // Remove a card from the hand or the deck for good, returning the entity to despawn
+    pub fn take(&mut self, key : &str) -> Option<Entity> {
+        let (st, _) = self.cards.get_mut(key)?;
+        let entity = if let CardStatus::Card(e, _) = *st { Some(e) } else { None };
+        *st = CardStatus::Played;
+        self.hand.retain(|k| k != key);
+        self.deck.retain(|k| k != key);
+        self.draw();
+        entity
+    }
+
+    // End of an act, every card is played unless they are kept for the next one
+    // Returns the entities to despawn
+    pub fn discard(&mut self, keep : bool) -> Vec<Entity> {
+        if keep {
+            return vec![];
+        }
+        let mut entities = vec![];
+        for (st, _) in self.cards.values_mut() {
+            if let CardStatus::Card(e, _) = *st {
+                entities.push(e);
+            }
+            *st = CardStatus::Played;
+        }
+        self.hand.clear();
+        self.deck.clear();
+        entities
+    }
+
+    // Play a card from the hand, returning the option it selects
+    // Cards that don't answer the current question select the "other" option, or the first one if there is none
+    pub fn play_card(&mut self, key : &str) -> Option<usize> {
+        let other_option = self.other_option.unwrap_or(0);
+        let (st, _) = self.cards.get_mut(key)?;
+        let opt = match st {
+            CardStatus::New(opt) => opt.unwrap_or(other_option),
+            CardStatus::Card(_, opt) => opt.unwrap_or(other_option),
+            CardStatus::Deck | CardStatus::Played => return None
+        };
+        *st = CardStatus::Played;
+        self.hand.retain(|k| k != key);
+        self.draw();
+        Some(opt)
+    }
+
+    // Mark all the cards of an important decision as played and end it, returning their keys
+    pub fn play_important(&mut self) -> Vec<String> {
+        let keys = std::mem::take(&mut self.important_decision);
+        for key in keys.iter() {
+            if let Some((st, _)) = self.cards.get_mut(key) {
+                if let CardStatus::Card(_, _) | CardStatus::New(_) = st {
+                    *st = CardStatus::Played; 
+                }
+            }
+        }
+        self.hand.retain(|k| !keys.contains(k));
+        self.draw();
+        keys
+    }
+
+    // Card played when the time of a timed question runs out: the default card if it is in the hand,
+    // else one that doesn't answer the question if there is an other option, else the card of the first option
+    // The ! of important cards is ignored when comparing with the default
+    pub fn timeout_card(&self, default : Option<&str>) -> Option<String> {
+        let option = |key : &String| match self.cards.get(key) {
+            Some((CardStatus::New(opt) | CardStatus::Card(_, opt), _)) => Some(*opt),
+            _ => None
+        };
+        let hand = || self.hand.iter().filter(move |k| self.important_decision.is_empty() || self.important_decision.contains(*k));
+
+        default.and_then(|d| hand().find(|k| k.trim_start_matches('!') == d.trim_start_matches('!')))
+            .or_else(|| self.other_option.and_then(|_| hand().find(|k| option(*k) == Some(None))))
+            .or_else(|| hand().filter_map(|k| option(k).flatten().map(|opt| (opt, k))).min().map(|(_, k)| k))
+            .cloned()
+    }
+
+    // Soften or skip a line of a node with a hidden topic, the same way in the game and headless
+    pub fn hide_line(&mut self, runner : &mut YarnRunner, node : &str, hidden : bool, is_question : bool) -> HiddenLine {
+        if !hidden {
+            self.skipped_node = None;
+            return HiddenLine::Shown;
+        }
+        if !matches!(&self.skipped_node, Some((n, _)) if n == node) {
+            if runner.set_node(&format!("{}Soft", node)).is_ok() {
+                return HiddenLine::Softened;
+            }
+            self.skipped_node = Some((node.to_string(), false));
+        }
+        match &mut self.skipped_node {
+            _ if is_question => HiddenLine::Shown,
+            Some((_, summarized)) if !*summarized => {
+                *summarized = true;
+                HiddenLine::Summary
+            },
+            _ => HiddenLine::Skipped
+        }
+    }
+
+    // Words of a card as they are shown on it
+    pub fn card_text(&self, key : &str) -> Option<String> {
+        let (_, words) = self.cards.get(key)?;
+        Some(words.iter().map(|w| match w {
+            WordType::Regular(s) | WordType::Varying(s) | WordType::PreviouslySelected(s) => s.as_str()
+        }).collect())
+    }
+}
+
+// Render resources owned by each card (render layer and image), released when the card is despawned
+#[derive(Resource)]
+pub struct CardRenderers {
+    free : Vec<u8>,
+    used : HashMap<Entity, (u8, Handle<Image>)>,
+    images : Vec<Handle<Image>>,
+}
+
+impl Default for CardRenderers {
+    fn default() -> Self {
+        CardRenderers {
+            free : (CARD_FIRST_LAYER..RenderLayers::TOTAL_LAYERS as u8).rev().collect(),
+            used : HashMap::new(),
+            images : vec![],
+        }
+    }
+}
+
+impl CardRenderers {
+    fn take(&mut self, card : Entity, image : &Handle<Image>) -> Option<u8> {
+        let layer = self.free.pop()?;
+        self.used.insert(card, (layer, image.clone()));
+        self.images.push(image.clone_weak());
+        Some(layer)
+    }
+
+    fn release(&mut self, card : Entity) -> Option<Handle<Image>> {
+        let (layer, image) = self.used.remove(&card)?;
+        self.free.push(layer);
+        Some(image)
+    }
+
+    // Card images that are still loaded (weak handles, so leaks show up here)
+    fn live_images(&self, images : &Assets<Image>) -> usize {
+        self.images.iter().filter(|h| images.contains(*h)).count()
+    }
+}
+
+// Script commands that are not handled by the dialogue, for other systems to react to
+pub struct DialogueCommand(pub Vec<String>);
+
+// Text of the dialogue box, revealed a few characters at a time
+#[derive(Resource, Default)]
+pub struct DialogueText {
+    speaker : String,
+    style : TextStyle,
+    line : String,
+    shown : f32,
+    skipped : bool,
+    // Who is talking and how, for the voice blips
+    pub voice : Option<String>,
+    pub emotion : Option<String>,
+}
+
+impl DialogueText {
+    pub fn show(&mut self, speaker : String, style : TextStyle, line : String) {
+        *self = DialogueText { speaker, style, line, ..default() };
+    }
+
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    // Number of characters revealed
+    pub fn revealed(&self) -> usize {
+        self.shown as usize
+    }
+
+    pub fn was_skipped(&self) -> bool {
+        self.skipped
+    }
+
+    pub fn is_revealing(&self) -> bool {
+        (self.shown as usize) < self.line.chars().count()
+    }
+
+    pub fn skip(&mut self) {
+        self.shown = self.line.chars().count() as f32;
+        self.skipped = true;
+    }
 }
 
 // ---
@@ -81,6 +425,10 @@ pub struct DialogueState {
 #[derive(Component)]
 pub struct DialogueBox;
 
+// Camera, text and background that draw a card, despawned with it
+#[derive(Component)]
+pub struct CardRenderer(Entity);
+
 #[derive(Component, Default, Debug)]
 pub struct DialogueCard {
     id : String,
@@ -99,7 +447,11 @@ impl DialogueCard {
         DialogueCard { id, image, style, ..default() }
     }
 
-    fn render(&mut self, render_layer : u8, cmd : &mut Commands, props : &Res<Props>) {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn render(&mut self, card : Entity, render_layer : u8, cmd : &mut Commands, props : &Res<Props>) {
         // Camera to render the 2d text onto the card image
         let text_pass_layer = RenderLayers::layer(render_layer);
         cmd.spawn((
@@ -111,7 +463,9 @@ impl DialogueCard {
                 },
                 ..default()
             },
-            text_pass_layer
+            UiCameraConfig { show_ui : false },
+            text_pass_layer,
+            CardRenderer(card)
         ));
 
         // Create image text
@@ -122,7 +476,8 @@ impl DialogueCard {
                 transform : Transform::from_xyz(0.5, 64., 0.1),
                 ..default()
             },
-            text_pass_layer
+            text_pass_layer,
+            CardRenderer(card)
         )).id());
 
         // Create card background sprite
@@ -132,7 +487,8 @@ impl DialogueCard {
                 transform : Transform::from_scale(Vec3::splat(8.)),
                 ..default()
             },
-            text_pass_layer
+            text_pass_layer,
+            CardRenderer(card)
         ));
 
         self.render_layer = Some(render_layer);
@@ -236,9 +592,14 @@ pub fn res_init(mut cmd : Commands,
                                 drink_textures, font });
 
     cmd.insert_resource(DialogueState::default());
+    cmd.insert_resource(CardRenderers::default());
+    cmd.insert_resource(DialogueText::default());
 
-    // Load dialogue
+    // Load dialogue, the metadata has the content warnings so it has to be there before the first line
     yarn.load("dialogue", &assets);
+    if let Some(metadata) = &yarn.metadata {
+        loading.0.push(metadata.clone_untyped());
+    }
 }
 
 // Dialogue box initialization
@@ -266,6 +627,7 @@ pub fn box_init(mut cmd : Commands,
             },
             ..default()
         },
+        UiCameraConfig { show_ui : false },
         box_pass_layer
     )); 
 
@@ -281,7 +643,9 @@ pub fn box_init(mut cmd : Commands,
             ..default()
         },
         DialogueBox{},
-        box_pass_layer
+        box_pass_layer,
+        accessibility::live(Role::StaticText),
+        accessibility::DialogueLine
     ));
     cmd.spawn((
         SpriteBundle {
@@ -311,108 +675,236 @@ pub fn box_init(mut cmd : Commands,
 // ---
 // Update systems
 
-fn hash_obj<T>(obj : T) -> u64 where T : Hash {
+// Start a new run of the story, shared by the game and the headless playthrough
+// Everything that is not saved between runs is reset here
+pub fn reset_run(runner : &mut YarnRunner, storage : &mut YarnStorage, state : &mut DialogueState) -> bool {
+    storage.clear();
+    *state = DialogueState::default();
+    runner.set_node("Start").is_ok()
+}
+
+// Remember the next line and the variables that changed after playing a card, shown in its tooltip in the next runs
+fn remember_consequence(story : &mut StoryState, storage : &mut PersistentStorage, played : PlayedCard, line : Option<String>, variables : &YarnStorage) {
+    let mut changes : Vec<String> = variables.iter().filter_map(|(k, v)| {
+        let before = played.variables.iter().find(|(b, _)| b == k).map(|(_, v)| v.as_str());
+        let after = v.to_string();
+        if before == Some(after.as_str()) { return None; }
+        let name = k.trim_start_matches('$');
+        match (before.unwrap_or("0").parse::<f32>(), after.parse::<f32>()) {
+            (Ok(b), Ok(a)) => Some(format!("{} {:+}", name, a - b)),
+            _ => Some(format!("{} is {}", name, after))
+        }
+    }).collect();
+    changes.sort();
+
+    let text = match (line, changes.is_empty()) {
+        (Some(line), true) => line,
+        (Some(line), false) => format!("{} ({})", line, changes.join(", ")),
+        (None, false) => changes.join(", "),
+        (None, true) => return
+    };
+    story.consequences.entry(played.question).or_default().insert(played.card, text);
+    if storage.0.set("consequences", &story.consequences).is_err() {
+        println!("Warning, problem saving the consequences");
+    }
+}
+
+// Functions for the script to branch on previous runs, they only remember in New Game+
+// ended_before takes the id of an ending and chose_before the key of a card
+pub fn story_function(name : &str, args : &[YarnValue], memory : Option<&RunMemory>) -> Option<YarnValue> {
+    let arg = args.first().map(|a| a.to_string());
+
+    let remembers = match name {
+        "new_game_plus" => memory.is_some(),
+        "ended_before" => {
+            let arg = arg?;
+            memory.is_some_and(|m| m.endings.contains(&arg))
+        },
+        "chose_before" => {
+            let arg = arg?;
+            memory.is_some_and(|m| m.chosen.contains(&arg))
+        },
+        _ => return None
+    };
+    Some(YarnValue::Bool(remembers))
+}
+
+pub fn hash_obj<T>(obj : T) -> u64 where T : Hash {
     let mut h = DefaultHasher::new();
     obj.hash(&mut h);
     h.finish()
 }
 
+// Split a yarn line into speaker and text, removing the [markup] tags
+// Also returns if the line is a question (has a ___ blank)
+pub fn parse_line(mut l : String) -> (Option<String>, String, bool) {
+    let is_question = l.contains("___");
+
+    while let Some(start) = l.find('[') {
+        let end = l.find(']').expect("Missing ] in option");
+        l.replace_range(start..end + 1, "");
+    }
+
+    let l : Vec<&str> = l.split(':').collect();
+    if l.len() == 1 {
+        (None, l[0].to_string(), is_question)
+    } else {
+        (Some(l[0].to_string()), l[1..].join(":"), is_question)
+    }
+}
+
+// The first markup tag of a line, like [happy], is the emotion it is said with
+pub fn line_emotion(l : &str) -> Option<String> {
+    let start = l.find('[')?;
+    let end = start + l[start..].find(']')?;
+    let tag = &l[start + 1..end];
+    (!tag.starts_with('/')).then(|| tag.to_string())
+}
+
+// The key of a card is the option without the (varying) words
+pub fn option_key(l : &str) -> String {
+    let key : Vec<&str> = l.split(' ')
+        .filter(|x| !x.contains('('))
+        .collect();
+    key.join(" ")
+}
+
+// Split the option into the words shown on the card
+fn option_words(l : &str, prev_sel : bool) -> Vec<WordType> {
+    let mut words = vec![];
+    for w in l.split(' ') {
+        if w.contains('(') {
+            words.push(WordType::Varying(w.replace(['(', ')'], "") + " "));
+            continue;
+        }
+        if words.last().is_some() && !matches!(words.last().unwrap(), WordType::Varying(_)) {
+            match words.last_mut().unwrap() {
+                WordType::Regular(s) => { s.push(' '); s.push_str(w); },
+                WordType::PreviouslySelected(s) => { s.push(' '); s.push_str(w); },
+                _ => ()
+            }
+        } else if prev_sel {
+            words.push(WordType::PreviouslySelected(w.to_string()));
+        } else {
+            words.push(WordType::Regular(w.to_string()));
+        }
+    }
+    words
+}
+
 // Handle the changes in dialogue updates
 pub fn update(mut cmd : Commands,
               mut state : ResMut<DialogueState>,
               mut story : ResMut<StoryState>,
-              keyboard : Res<Input<KeyCode>>,
-              mouse : Res<Input<MouseButton>>,
+              actions : Res<Actions>,
               props : Res<Props>,
               time : Res<Time>,
               mut storage : ResMut<PersistentStorage>,
-              asset_lines : Res<Assets<YarnLinesAsset>>,
-              mut asset_runner : ResMut<Assets<YarnRunnerAsset>>,
+              mut replay : ResMut<Replay>,
+              (asset_lines, mut asset_runner) : (Res<Assets<YarnLinesAsset>>, ResMut<Assets<YarnRunnerAsset>>),
+              (endings, definitions, mut checkpoints, mut game_state) : (Res<Endings>, Res<Assets<EndingsAsset>>, ResMut<Checkpoints>, ResMut<NextState<GameState>>),
               mut yarn : ResMut<YarnManager>,
               mut materials : ResMut<Assets<StandardMaterial>>,
-              mut dialogue_box : Query<&mut Text, With<DialogueBox>>,
-              cards : Query<(Entity, &DialogueCard)>,
-              mut wait_timer : Local<(f32, f32)>,
-              mut other_option : Local<usize>) {
+              mut text : ResMut<DialogueText>,
+              mut commands : EventWriter<DialogueCommand>,
+              (settings, topics, topic_defs, metadata) : (Res<Settings>, Res<Topics>, Res<Assets<TopicsAsset>>, Res<Assets<YarnMetadataAsset>>),
+              cards : Query<(Entity, &DialogueCard)>) {
     // Get the assets for the dialogue manager and check that they are loaded
     let (runner, lines) = match get_yarn_components(&yarn, &mut asset_runner, &asset_lines) {
         None => return,
         Some(v) => v
     };
 
+    replay.tick(time.delta_seconds());
+
+    // Continuing after an ending goes back to the menu through the restart
     if yarn.finished {
-        if keyboard.just_pressed(KeyCode::Space) {
+        if replay.continued(actions.just_pressed(Action::Continue)) {
             story.the_end = true;
+            game_state.set(GameState::Restart);
         }
         return;
     }
 
     // Advance the wait timer
-    if wait_timer.0 <= wait_timer.1 {
-        wait_timer.0 += time.delta_seconds();
+    if state.wait_timer.0 <= state.wait_timer.1 {
+        state.wait_timer.0 += time.delta_seconds();
         return;
     }
 
-    // Select a card using your mouse
-    if yarn.waiting_response && mouse.just_pressed(MouseButton::Left) && state.selected_card.is_some() {
+    // Timed questions play a card when the time runs out, the same way as if it was confirmed
+    let mut timed_out = false;
+    if yarn.waiting_response {
+        if let Some((left, _)) = &mut state.choice_timer {
+            *left -= time.delta_seconds();
+            timed_out = *left <= 0.;
+        }
+    }
+    if timed_out {
+        let default = state.choice_timer.take().and_then(|(_, d)| d);
+        let key = state.timeout_card(default.as_deref());
+        state.previous_card = state.selected_card;
+        state.selected_card = key.and_then(|key| cards.iter().find(|(_, card)| card.id == key)).map(|(e, _)| e);
+        if state.selected_card.is_none() {
+            println!("Warning, time is up but there is no card to play");
+        }
+    }
+
+    // Play the selected card
+    let mut answered = false;
+    if yarn.waiting_response && (timed_out || replay.confirmed(actions.just_pressed(Action::Confirm))) && state.selected_card.is_some() {
         let id = state.selected_card.unwrap();
+        answered = true;
 
         let (_, card) = cards.get(id).expect("Error loading card with selected card id");
 
         cmd.entity(id).despawn();
         yarn.waiting_response = false;
-
-        let state_card = &mut state.cards.get_mut(&card.id);
-        if let Some((st, _)) = state_card {
-            if let CardStatus::Card(_, opt) = st {
-                let opt = if opt.is_some() {opt.unwrap()} else {*other_option};
-                runner.select_option(opt).unwrap();
-                println!("Selected option {} with card {}", opt, card.id);
-                *st = CardStatus::Played;
-
-                let question = story.current_question;
-                let opts = story.selected_options.entry(question).or_insert(vec![]);
-                opts.push(card.id.clone());
-                if storage.0.set("selected_options", &story.selected_options).is_err() {
-                    println!("Warning, problem saving selected options");
-                };
-            }
+        state.choice_timer = None;
+
+        if let Some(opt) = state.play_card(&card.id) {
+            runner.select_option(opt).unwrap();
+            println!("Selected option {} with card {}", opt, card.id);
+            replay.record_select(&card.id);
+
+            let question = story.current_question;
+            let opts = story.selected_options.entry(question).or_insert(vec![]);
+            opts.push(card.id.clone());
+            if storage.0.set("selected_options", &story.selected_options).is_err() {
+                println!("Warning, problem saving selected options");
+            };
+
+            let variables = yarn.storage.iter().map(|(k, v)| (k.clone(), v.to_string())).collect();
+            state.played = Some(PlayedCard { question, card : card.id.clone(), variables });
         }
         state.selected_card = None;
         state.previous_card = None;
+    }
 
-        if yarn.important_decision {
-            let card_a = &state.important_decision[0].clone().unwrap();
-            let card_b = &state.important_decision[1].clone().unwrap();
-            // TODO: Despawn the cards
-
-            for (id, card) in cards.iter() {
-                if card.id == *card_a || card.id == *card_b {
-                    cmd.entity(id).despawn();
-                }
-            }
-
-            let card_a = state.cards.get_mut(&card_a.clone());
-            if let Some((st, _)) = card_a {
-                if let CardStatus::Card(_, _) = st {
-                    *st = CardStatus::Played; 
-                }
-            }
-
-            let card_b = state.cards.get_mut(&card_b.clone());
-            if let Some((st, _)) = card_b {
-                if let CardStatus::Card(_, _) = st {
-                    *st = CardStatus::Played; 
-                }
+    // Only the chosen card of an important decision is played, the rest are discarded after answering
+    if yarn.important_decision && !yarn.waiting_response {
+        // Despawn the cards
+        let important = state.play_important();
+        for (id, card) in cards.iter() {
+            if important.contains(&card.id) {
+                cmd.entity(id).despawn();
             }
-
-            yarn.important_decision = false;
         }
+
+        yarn.important_decision = false;
     }
 
     // Check if the dialogue is paused and if the user is continuing
-    if keyboard.just_pressed(KeyCode::Space) {
-        yarn.waiting_continue = false;
+    // Continuing while the line is being revealed shows all of it, which is not recorded since the dialogue doesn't go on
+    // Confirm and Continue share Return and South, so the press that answers a question doesn't continue too
+    if yarn.waiting_continue && !answered {
+        if text.is_revealing() {
+            if replay.skipped(actions.just_pressed(Action::Continue)) {
+                text.skip();
+            }
+        } else if replay.continued(actions.just_pressed(Action::Continue)) {
+            yarn.waiting_continue = false;
+        }
     }
     if yarn.waiting_continue || yarn.waiting_response {
         return;
@@ -422,99 +914,79 @@ pub fn update(mut cmd : Commands,
     if let Ok(Some(dialogue)) = runner.execute(&mut yarn.storage) {
         match dialogue {
             ExecutionOutput::Line(line) => {
-                let mut l = lines.line(&line).expect("Failed to parse yarn line");
-                let is_question = l.contains("___");
-
-                while let Some(start) = l.find('[') {
-                    let end = l.find(']').expect("Missing ] in option");
-                    l.replace_range(start..end + 1, "");
+                replay.line(&line.id);
+                let raw = lines.line(&line).expect("Failed to parse yarn line");
+                let node = line_node(&line.id).unwrap_or_default();
+                let emotion = line_emotion(&raw);
+                let (speaker, line, is_question) = parse_line(raw);
+                let voice = speaker.clone();
+
+                // Soften or skip the nodes with topics the player chose to hide (the ones of the log while replaying)
+                // The player is told what was hidden, before the softened node or instead of the lines
+                let hidden_topics = replay.hidden_topics(&settings);
+                let topic = match (yarn.metadata.as_ref().and_then(|m| metadata.get(m)), topic_defs.get(&topics.0)) {
+                    (Some(metadata), Some(topics)) => topics.hidden(node, metadata, &hidden_topics),
+                    _ => None
+                };
+                let hidden = state.hide_line(runner, node, topic.is_some(), is_question);
+                if let Some(topic) = topic {
+                    let notice = match hidden {
+                        HiddenLine::Softened => Some(format!("(Softened, {})", topic.name)),
+                        HiddenLine::Summary => Some(format!("(Skipped, {}: {})", topic.name, topic.summary)),
+                        _ => None
+                    };
+                    if let Some(notice) = notice {
+                        text.show("".to_string(), props.box_style["regular"].clone(), notice);
+                        yarn.waiting_continue = true;
+                    }
+                }
+                if hidden != HiddenLine::Shown {
+                    return;
                 }
 
-                let l : Vec<&str> = l.split(':').collect();
-
-                let (speaker, style) = if l.len() == 1 {
-                    println!("Warning, line without speaker");
-                    ("".to_string(), props.box_style["regular"].clone())
-                } else if !["Remie", "Player", "Waiter"].contains(&l[0]) {
-                    println!("Warning, the speaker name is misspelled {}", l[0]);
-                    (l[0].to_string() + "\n", props.box_style["regular"].clone())
-                } else {
-                    (l[0].to_string() + "\n", props.box_style[l[0]].clone())
+                let (speaker, style) = match speaker {
+                    None => {
+                        println!("Warning, line without speaker");
+                        ("".to_string(), props.box_style["regular"].clone())
+                    },
+                    Some(s) if !["Remie", "Player", "Waiter"].contains(&s.as_str()) => {
+                        println!("Warning, the speaker name is misspelled {}", s);
+                        (s + "\n", props.box_style["regular"].clone())
+                    },
+                    Some(s) => {
+                        let style = props.box_style[s.as_str()].clone();
+                        (s + "\n", style)
+                    }
                 };
-                let line = if l.len() == 1 { l[0].to_string() } else { l[1..].join(":") };
 
                 if is_question {
                     story.current_question = hash_obj(&line);
                 }
 
-                dialogue_box.single_mut().sections[0] = TextSection::new(speaker, style);
-                dialogue_box.single_mut().sections[1].value = line;
+                if let Some(played) = state.played.take() {
+                    let said = match &voice { Some(v) => format!("{}: {}", v, line.trim()), None => line.trim().to_string() };
+                    remember_consequence(&mut story, &mut storage, played, Some(said), &yarn.storage);
+                }
+
+                text.show(speaker, style, line);
+                text.voice = voice;
+                text.emotion = emotion;
 
                 yarn.waiting_continue = !is_question;
             },
             ExecutionOutput::Options(opts) => {
-                for (_, (t, _)) in state.cards.iter_mut() {
-                    if let CardStatus::Card(_, opt) = t {
-                        *opt = None;
-                    }
+                if let Some(played) = state.played.take() {
+                    remember_consequence(&mut story, &mut storage, played, None, &yarn.storage);
                 }
-                let question = story.current_question;
-
-                for (opt_num, opt) in opts.iter().enumerate() {
-                    let line = lines.line(opt.line()).expect("Failed to parse yarn option");
-
-                    for l in line.split('|') {
-                        let l = l.trim();
-                        if l == "other" {
-                            *other_option = opt_num;
-                            continue;
-                        }
 
-                        let key : Vec<&str> = l.split(' ')
-                            .filter(|x| !x.contains('('))
-                            .collect();
-                        let key = key.join(" ");
-                        let prev_sel = story.selected_options.entry(question).or_default().contains(&key);
-
-                        if l.starts_with('!') {
-                            // Just two cards, hide the others
-                            if state.important_decision[0].is_none() {
-                                state.important_decision[0] = Some(key.to_string());
-                            } else {
-                                state.important_decision[1] = Some(key.to_string());
-                            }
-
-                            yarn.important_decision = true;
-                        } 
-
-                        let mut words = vec![];
-                        for w in l.split(' ') {
-                            if w.contains('(') {
-                                words.push(WordType::Varying(w.replace(['(', ')'], "") + " "));
-                                continue;
-                            }
-                            if words.last().is_some() && !matches!(words.last().unwrap(), WordType::Varying(_)) {
-                                match words.last_mut().unwrap() {
-                                    WordType::Regular(s) => { s.push(' '); s.push_str(w); },
-                                    WordType::PreviouslySelected(s) => { s.push(' '); s.push_str(w); },
-                                    _ => ()
-                                }
-                            } else if prev_sel {
-                                words.push(WordType::PreviouslySelected(w.to_string()));
-                            } else {
-                                words.push(WordType::Regular(w.to_string()));
-                            }
-                        }
+                let question = story.current_question;
+                let previous = story.selected_options.entry(question).or_default().clone();
 
-                        let (t, w) = state.cards.entry(key.to_string()).or_default();                        
-                        *w = words;
-                        match t {
-                            CardStatus::New(o) => *o = Some(opt_num),
-                            CardStatus::Card(_, o) => *o = Some(opt_num),
-                            _ => ()
-                        }
-                        println!("Option {} with key {}", opt_num, key);
-                    }
+                let options : Vec<String> = opts.iter()
+                    .map(|opt| lines.line(opt.line()).expect("Failed to parse yarn option"))
+                    .collect();
+                if state.add_options(&options, &previous, replay.memory(&story).is_some()) {
+                    yarn.important_decision = true;
                 }
 
                 yarn.waiting_response = true;
@@ -523,20 +995,31 @@ pub fn update(mut cmd : Commands,
                 let c : Vec<&str> = c.split(' ').collect();
                 match c[0] {
                     "discard" => {
-                        state.cards.iter_mut().for_each(|(_, (card, _))| {
-                            if let CardStatus::Card(id, _) = card {
-                                cmd.entity(*id).despawn();
-                            } 
-                            *card = CardStatus::Played;
-                        });
+                        // <<discard keep>> starts a new act keeping the cards that were not played
+                        let keep = c.get(1) == Some(&"keep");
+                        state.discard(keep).into_iter().for_each(|e| cmd.entity(e).despawn());
                         state.selected_card = None;
                         state.previous_card = None;
 
-                        dialogue_box.single_mut().sections[0].value = "".to_string(); 
-                        dialogue_box.single_mut().sections[1].value = "New act (cards are discarded)".to_string();
+                        let message = if keep { "New act (cards are kept)" } else { "New act (cards are discarded)" };
+                        text.show("".to_string(), props.box_style["regular"].clone(), message.to_string());
 
                         yarn.waiting_continue = true;
                     },
+                    "give" => {
+                        assert!(c.len() >= 2, "Error parsing give");
+                        state.give(&c[1..].join(" "));
+                    },
+                    "take" => {
+                        assert!(c.len() >= 2, "Error parsing take");
+                        if let Some(e) = state.take(&option_key(&c[1..].join(" "))) {
+                            cmd.entity(e).despawn();
+                            if state.selected_card == Some(e) {
+                                state.selected_card = None;
+                                state.previous_card = None;
+                            }
+                        }
+                    },
                     "marcoComes" => {
                         story.is_marco_here = true;
                     },
@@ -590,37 +1073,73 @@ pub fn update(mut cmd : Commands,
                             }
                         }
                     },
+                    "timed" => {
+                        // <<timed seconds default card>> for the next question
+                        assert!(c.len() >= 2, "Error parsing timed");
+                        let seconds = c[1].parse::<f32>().expect("Error converting string");
+                        let default = (c.len() > 2).then(|| option_key(&c[2..].join(" ")));
+                        state.choice_timer = Some((seconds, default));
+                    },
+                    "checkpoint" => {
+                        assert!(c.len() >= 2, "Error parsing checkpoint");
+                        checkpoints.reach(Checkpoint::new(c[1], &c[2..].join(" "), &yarn.storage, &state, &story));
+                    },
                     "remieLeaves" => {
                         story.is_remie_here = false;
                     },
                     "wait" => {
-                        *wait_timer = (0., if c.len() > 1 { c[1].parse::<f32>().expect("Error converting string") } else { 1. });
-                        dialogue_box.single_mut().sections[0].value = "".to_string(); 
-                        dialogue_box.single_mut().sections[1].value = "...".to_string();
+                        state.wait_timer = (0., if c.len() > 1 { c[1].parse::<f32>().expect("Error converting string") } else { 1. });
+                        text.show("".to_string(), props.box_style["regular"].clone(), "...".to_string());
                     },
                     "theEnd" => {
                         assert!(c.len() == 2, "Error parsing theEnd");
-                        let num = c[1].parse::<usize>().expect("Error parsing ending number") - 1;
-                        assert!(num < NUM_ENDINGS, "There are too many endings!");
-                        story.endings[num] = true;
-                        if storage.0.set("unlocked_endings", &story.endings).is_err() {
+                        if definitions.get(&endings.0).is_some_and(|d| d.get(c[1]).is_none()) {
+                            println!("Warning, the ending {} is not defined", c[1]);
+                        }
+                        story.endings.insert(c[1].to_string());
+                        if storage.0.set("endings", &story.endings).is_err() {
                             println!("Warning, problem saving unlocked endings");
                         }
-                        dialogue_box.single_mut().sections[0].value = "".to_string(); 
-                        dialogue_box.single_mut().sections[1].value = "the end... or is it".to_string();
+                        text.show("".to_string(), props.box_style["regular"].clone(), "the end... or is it".to_string());
                         yarn.finished = true;
                     },
-                    _ => println!("TODO: Command not implemented {}", c[0])
+                    _ => commands.send(DialogueCommand(c.iter().map(|x| x.to_string()).collect()))
                 }
             },
             ExecutionOutput::Function(function) => {
-                let output = yarn_spinner::handle_default_functions(&function);
-                runner.return_function(output.unwrap().unwrap()).unwrap();
+                let output = match yarn_spinner::handle_default_functions(&function) {
+                    Some(output) => output.unwrap(),
+                    None => story_function(&function.name, &function.args, replay.memory(&story).as_ref()).expect("Unknown yarn function")
+                };
+                runner.return_function(output).unwrap();
             }
         }
     }
 }
 
+// Reveal the dialogue line at the speed from the settings
+pub fn text_update(time : Res<Time>,
+                   settings : Res<Settings>,
+                   mut text : ResMut<DialogueText>,
+                   mut dialogue_box : Query<&mut Text, With<DialogueBox>>) {
+    if text.is_revealing() {
+        if settings.text_speed > 0. {
+            text.shown += time.delta_seconds() * settings.text_speed;
+        } else {
+            text.skip();
+        }
+    } else if !text.is_changed() && !settings.is_changed() {
+        return;
+    }
+
+    let Ok(mut dialogue_box) = dialogue_box.get_single_mut() else { return; };
+    let mut style = text.style.clone();
+    style.font_size = settings.font_size;
+    dialogue_box.sections[0] = TextSection::new(text.speaker.clone(), style);
+    dialogue_box.sections[1].value = text.line.chars().take(text.shown as usize).collect();
+    dialogue_box.sections[1].style.font_size = settings.font_size;
+}
+
 // Create the cards requested
 pub fn create_cards_update(mut cmd : Commands,
                            props : Res<Props>,
@@ -628,6 +1147,16 @@ pub fn create_cards_update(mut cmd : Commands,
                            mut images : ResMut<Assets<Image>>,
                            mut materials: ResMut<Assets<StandardMaterial>>,
                            player : Query<Entity, With<Player>>) {
+    // Cards that went back to the deck
+    let returned = std::mem::take(&mut state.returned);
+    for e in returned {
+        cmd.entity(e).despawn();
+        if state.selected_card == Some(e) {
+            state.selected_card = None;
+            state.previous_card = None;
+        }
+    }
+
     for (word, (card, _)) in state.cards.iter_mut() {
         if let CardStatus::New(opt) = card {
             let mut image = Image { texture_descriptor : props.card_texture_descriptor.clone(), ..default() };
@@ -656,6 +1185,7 @@ pub fn create_cards_update(mut cmd : Commands,
 // Update the cards with new words
 pub fn card_words_update(state : ResMut<DialogueState>,
                          props : ResMut<Props>,
+                         yarn : Res<YarnManager>,
                          cards : Query<&DialogueCard>,
                          mut text : Query<&mut Text>) {
     for card in cards.iter() {
@@ -669,6 +1199,11 @@ pub fn card_words_update(state : ResMut<DialogueState>,
                     WordType::Varying(w) => TextSection::new(w, props.card_style["varying"].clone()),
                     WordType::PreviouslySelected(w) => TextSection::new(w, props.card_style["previously_selected"].clone()),
                 }).collect();
+
+                // Countdown of timed questions
+                if let (Some((left, _)), true) = (&state.choice_timer, yarn.waiting_response) {
+                    t.sections.push(TextSection::new(format!("\n\n{}", left.max(0.).ceil()), props.card_style["varying"].clone()));
+                }
             }
         }
     }
@@ -680,28 +1215,30 @@ pub fn card_update(mut cmd : Commands,
                    props : Res<Props>,
                    state : Res<DialogueState>,
                    yarn : Res<YarnManager>,
-                   mut cards : Query<(Entity, &mut DialogueCard, &mut Transform), Without<Player>>,
-                   mut render_layer : Local<u8>) {
-    if *render_layer == 0 { *render_layer = 2 };
-
+                   settings : Res<Settings>,
+                   mut renderers : ResMut<CardRenderers>,
+                   mut cards : Query<(Entity, &mut DialogueCard, &mut Transform), Without<Player>>) {
     let n = cards.iter().count();
     for (i, (e, mut card, mut trans)) in cards.iter_mut().enumerate() {
         if !card.has_renderer {
-            *render_layer += 1;
-            assert!(*render_layer < 32, "Can't have more than 32 render layers");
-            card.render(*render_layer, &mut cmd, &props); 
-            card.previous_trans = *trans;
-            card.lerp_time = 0.;
-            card.has_renderer = true;
+            // If there are no free layers, try again when a card is released
+            if let Some(render_layer) = renderers.take(e, &card.image) {
+                card.render(e, render_layer, &mut cmd, &props); 
+                card.previous_trans = *trans;
+                card.lerp_time = 0.;
+                card.has_renderer = true;
+            }
         }
 
         if yarn.important_decision {
-            if state.important_decision[0].is_some() && state.important_decision[0].clone().unwrap() == card.id {
-                card.target_trans.translation = Vec3::new(-0.15, 0., -1.);
-            } else if state.important_decision[1].is_some() && state.important_decision[1].clone().unwrap() == card.id {
-                card.target_trans.translation = Vec3::new(0.15, 0., -1.); 
-            } else {
-                card.target_trans.translation = Vec3::new(0., -0.8, 0.);
+            // The cards of the decision are spread in a row and the rest are hidden below
+            let important = state.important_decision.len();
+            match state.important_decision.iter().position(|k| *k == card.id) {
+                Some(j) => {
+                    let offset = j as f32 - (important as f32 - 1.) / 2.;
+                    card.target_trans.translation = Vec3::new(offset * IMPORTANT_PADDING.min(0.9 / important as f32), 0., -1.);
+                },
+                None => card.target_trans.translation = Vec3::new(0., -0.8, 0.)
             }
         } else {
             let offset = i as f32 - (n as f32 - 1.) / 2.;
@@ -737,6 +1274,11 @@ pub fn card_update(mut cmd : Commands,
             card.lerp_time = 0.;
         }
 
+        // Without motion the cards jump straight to their place
+        if settings.reduced_motion {
+            *trans = card.target_trans;
+        }
+
         if card.target_trans != *trans {
             card.lerp_time += time.delta_seconds();
             card.lerp_time = card.lerp_time.min(CARD_LERP_TIME);
@@ -746,6 +1288,37 @@ pub fn card_update(mut cmd : Commands,
     } 
 }
 
+// Release the render resources of despawned cards
+pub fn release_cards_update(mut cmd : Commands,
+                            mut renderers : ResMut<CardRenderers>,
+                            mut images : ResMut<Assets<Image>>,
+                            mut removed : RemovedComponents<DialogueCard>,
+                            owned : Query<(Entity, &CardRenderer)>) {
+    for card in removed.iter() {
+        for (e, CardRenderer(owner)) in owned.iter() {
+            if *owner == card {
+                cmd.entity(e).despawn();
+            }
+        }
+        if let Some(image) = renderers.release(card) {
+            images.remove(&image);
+        }
+    }
+    renderers.images.retain(|h| images.contains(h));
+}
+
+// Debug counter of live card cameras and images, to check that nothing leaks after a restart
+pub fn card_debug_update(renderers : Res<CardRenderers>,
+                         images : Res<Assets<Image>>,
+                         cameras : Query<(), (With<Camera>, With<CardRenderer>)>,
+                         mut count : Local<(usize, usize)>) {
+    let live = (cameras.iter().count(), renderers.live_images(&images));
+    if live != *count {
+        println!("Card renderers: {} cameras, {} images", live.0, live.1);
+        *count = live;
+    }
+}
+
 fn intersect(plane_center : Vec3, plane_normal : Vec3,
              view_pos : Vec3, view_dir : Vec3) -> Option<(f32, Vec3)> {
     if view_dir.dot(plane_normal) == 0. { 
@@ -757,12 +1330,52 @@ fn intersect(plane_center : Vec3, plane_normal : Vec3,
     Some((d, p))
 }
 
-// Pick cards using a mouse raycaster
+// Pick cards using a mouse raycaster, or navigate through them with the keyboard or gamepad
 pub fn pick_card_update(mut state : ResMut<DialogueState>,
+                        replay : Res<Replay>,
+                        actions : Res<Actions>,
+                        yarn : Res<YarnManager>,
                         cam : Query<(&Camera, &GlobalTransform), With<Camera3d>>,
-                        cards : Query<(Entity, &GlobalTransform), With<DialogueCard>>,
+                        cards : Query<(Entity, &GlobalTransform, &DialogueCard)>,
                         window : Query<&Window>,
                         mut mouse_prev : Local<Vec2>) {
+    // When replaying, hover the card that is going to be played next
+    if replay.is_replaying() {
+        state.previous_card = state.selected_card;
+        state.selected_card = replay.next_card()
+            .and_then(|key| cards.iter().find(|(_, _, card)| card.id == key))
+            .map(|(e, _, _)| e);
+        return;
+    }
+
+    // Move the selection through the hand, ordered from left to right
+    state.previous_card = state.selected_card;
+    if actions.just_pressed(Action::Cancel) {
+        state.selected_card = None;
+    }
+
+    let dir = if actions.just_pressed(Action::Left) { -1 } else if actions.just_pressed(Action::Right) { 1 } else { 0 };
+    if dir != 0 {
+        let mut hand : Vec<(Entity, f32)> = cards.iter()
+            .filter(|(_, _, card)| !yarn.important_decision || state.important_decision.contains(&card.id))
+            .map(|(e, _, card)| (e, card.target_trans.translation.x))
+            .collect();
+        hand.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+        if !hand.is_empty() {
+            let next = match state.selected_card.and_then(|s| hand.iter().position(|(e, _)| *e == s)) {
+                Some(i) => (i as i32 + dir).clamp(0, hand.len() as i32 - 1) as usize,
+                None => if dir > 0 { 0 } else { hand.len() - 1 }
+            };
+            state.selected_card = Some(hand[next].0);
+        }
+    }
+
+    // The mouse only takes over when it is used again
+    if !actions.using_mouse {
+        return;
+    }
+
     // Get the mouse world position
     let Some(mouse_pos) = window.single().cursor_position() else { return; };
     *mouse_prev = mouse_pos;
@@ -774,7 +1387,7 @@ pub fn pick_card_update(mut state : ResMut<DialogueState>,
     let mut cards_hovered = HashMap::new();
     let card_bounds = Vec3::from((CARD_MESH_SIZE * 0.5, 0.));
 
-    for (e, trans) in cards.iter() {
+    for (e, trans, _) in cards.iter() {
         let (_, r, t) = trans.to_scale_rotation_translation();
         let normal = r.mul_vec3(Vec3::Z);
 
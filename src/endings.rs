@@ -0,0 +1,189 @@
+// Ending definitions and the endings gallery on the main menu
+
+use super::{Props, StoryState, PersistentStorage, menu::{MenuButton, spawn_button, text_style}};
+use bevy::{
+    prelude::*,
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    utils::BoxedFuture,
+    reflect::TypeUuid
+};
+use serde::Deserialize;
+
+// ---
+// Constants
+
+const GALLERY_BACKGROUND : Color = Color::rgb(0.05, 0.12, 0.08);
+const IMAGE_SLOT : Color = Color::rgba(0., 0., 0., 0.3);
+const LOCKED_TEXT : Color = Color::rgb(0.5, 0.5, 0.5);
+
+// ---
+// Plugin
+
+pub struct EndingsPlugin;
+
+impl Plugin for EndingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<EndingsAsset>()
+           .init_asset_loader::<EndingsAssetLoader>();
+    }
+}
+
+// ---
+// Resources
+
+#[derive(Deserialize)]
+pub struct Ending {
+    pub id : String,
+    pub title : String,
+    pub epilogue : String,
+    pub hint : String,
+    pub image : Option<String>,
+}
+
+// Handle to the ending definitions
+#[derive(Resource)]
+pub struct Endings(pub Handle<EndingsAsset>);
+
+// ---
+// Assets
+
+#[derive(TypeUuid, Deserialize)]
+#[uuid = "0b6c5a5e-3a0e-4c1b-9d8e-7f1f4b9a2c31"]
+pub struct EndingsAsset(pub Vec<Ending>);
+
+impl EndingsAsset {
+    // Ending named by its id in a theEnd command
+    pub fn get(&self, id : &str) -> Option<&Ending> {
+        self.0.iter().find(|e| e.id == id)
+    }
+}
+
+#[derive(Default)]
+struct EndingsAssetLoader;
+
+impl AssetLoader for EndingsAssetLoader {
+    fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let endings = ron::de::from_bytes::<EndingsAsset>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(endings));
+            Ok(())
+        })
+    }
+    fn extensions(&self) -> &[&str] { &["endings.ron"] }
+}
+
+// ---
+// Components
+
+#[derive(Component)]
+pub struct GalleryNode;
+
+// ---
+// State transitions
+
+// Build the gallery with the endings unlocked so far
+pub fn enter_gallery(mut cmd : Commands,
+                     assets : Res<AssetServer>,
+                     props : Res<Props>,
+                     story : Res<StoryState>,
+                     endings : Res<Endings>,
+                     definitions : Res<Assets<EndingsAsset>>) {
+    let Some(EndingsAsset(definitions)) = definitions.get(&endings.0) else {
+        println!("Warning, the endings are not loaded");
+        return;
+    };
+    for id in story.endings.iter().filter(|id| !definitions.iter().any(|e| e.id == **id)) {
+        println!("Warning, the unlocked ending {} has no definition", id);
+    }
+
+    cmd.spawn((
+        NodeBundle {
+            style : Style {
+                size : Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                align_items : AlignItems::Center,
+                justify_content : JustifyContent::Center,
+                flex_direction: FlexDirection::Column,
+                gap : Size::new(Val::Auto, Val::Px(16.0)),
+                ..default()
+            },
+            background_color : GALLERY_BACKGROUND.into(),
+            ..default()
+        },
+        GalleryNode
+    ))
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section("Endings", text_style(&props, 48.0)));
+
+        for (i, ending) in definitions.iter().enumerate() {
+            let unlocked = story.endings.contains(&ending.id);
+
+            parent.spawn(NodeBundle {
+                style : Style {
+                    align_items : AlignItems::Center,
+                    gap : Size::new(Val::Px(16.0), Val::Auto),
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|row| {
+                // Illustration slot, empty until the ending is unlocked
+                let slot = Style { size : Size::new(Val::Px(80.0), Val::Px(80.0)), ..default() };
+                match &ending.image {
+                    Some(image) if unlocked => {
+                        row.spawn(ImageBundle { style : slot, image : assets.load(image.as_str()).into(), ..default() });
+                    },
+                    _ => {
+                        row.spawn(NodeBundle { style : slot, background_color : IMAGE_SLOT.into(), ..default() });
+                    }
+                }
+
+                let (title, text) = if unlocked {
+                    (ending.title.clone(), ending.epilogue.clone())
+                } else {
+                    ("???".to_string(), format!("Hint: {}", ending.hint))
+                };
+                let mut small = text_style(&props, 16.0);
+                if !unlocked { small.color = LOCKED_TEXT; }
+
+                row.spawn(NodeBundle {
+                    style : Style {
+                        flex_direction : FlexDirection::Column,
+                        size : Size::width(Val::Px(520.0)),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|column| {
+                    column.spawn(TextBundle::from_section(format!("{}. {}", i + 1, title), text_style(&props, 24.0)));
+                    column.spawn(TextBundle::from_section(text, small));
+                });
+            });
+        }
+
+        spawn_button(parent, "Back", text_style(&props, 24.0), MenuButton::Back);
+    });
+}
+
+// ---
+// Update systems
+
+// Old saves stored the endings as a list of flags in the order of the file, convert them to ids
+pub fn migrate_endings(mut story : ResMut<StoryState>,
+                       mut storage : ResMut<PersistentStorage>,
+                       endings : Res<Endings>,
+                       definitions : Res<Assets<EndingsAsset>>) {
+    let Ok(unlocked) = storage.0.get::<Vec<bool>>("unlocked_endings") else { return; };
+    let Some(EndingsAsset(definitions)) = definitions.get(&endings.0) else { return; };
+    if unlocked.is_empty() { return; }
+
+    for (ending, _) in definitions.iter().zip(unlocked.iter()).filter(|(_, u)| **u) {
+        story.endings.insert(ending.id.clone());
+    }
+    if storage.0.set("endings", &story.endings).is_err() || storage.0.set("unlocked_endings", &Vec::<bool>::new()).is_err() {
+        println!("Warning, problem migrating the unlocked endings");
+    }
+}
+
+pub fn exit_gallery(mut cmd : Commands, node : Query<Entity, With<GalleryNode>>) {
+    node.iter().for_each(|x| cmd.entity(x).despawn_recursive());
+}
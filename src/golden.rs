@@ -0,0 +1,260 @@
+// Golden transcript regression tests for the script
+// Every <name>.choices file lists the cards to play (one per line, # for comments),
+// with the same header as a replay log for hidden topics and New Game+ runs, and <name>.replay files are logs recorded in game
+// The headless transcript has to match the checked-in <name>.transcript
+// Each one is also played twice with a restart in between, to check that nothing leaks into the next run,
+// and restarted through the game state to check that the in-game restart resets the dialogue and the cards
+// The lines are also read aloud with the recording speech backend to check what text to speech says
+
+use super::{GameState, StoryState, restart, replay::{Replay, ReplayHeader, Playthrough, ReplayEvent, read_events}, dialogue::{DialogueState, DialogueText, DialogueCard, Drinks}, settings::Settings, speech::{Speech, RecordingBackend, speech_update}, yarn::*};
+use bevy::prelude::*;
+use yarn_spinner::YarnStorage;
+use std::{fs, path::PathBuf, collections::{HashMap, HashSet}, sync::{Arc, Mutex}};
+
+// ---
+// Constants
+
+const GOLDEN_DIR : &str = "tests/golden";
+
+// ---
+// Functions
+
+// Read the golden options from the command line
+// --golden [dir] compares the transcripts, adding --bless rewrites them with the current script
+pub fn from_args(args : &[String]) -> Option<i32> {
+    let i = args.iter().position(|a| a == "--golden")?;
+    let dir = args.get(i + 1).filter(|d| !d.starts_with("--")).map_or(GOLDEN_DIR, |d| d.as_str());
+    Some(run(dir, args.iter().any(|a| a == "--bless")))
+}
+
+fn choice_events(data : &str) -> Vec<ReplayEvent> {
+    data.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|card| ReplayEvent::Select { time : 0., line : String::new(), card : card.to_string() })
+        .collect()
+}
+
+fn playthrough(header : &ReplayHeader) -> Result<Playthrough, String> {
+    let mut play = Playthrough::load("dialogue")?;
+    play.header = header.clone();
+    Ok(play)
+}
+
+// Full transcript of a playthrough: lines, played cards, commands, variables and ending
+pub fn transcript(events : &[ReplayEvent], header : &ReplayHeader) -> Result<String, String> {
+    let mut play = playthrough(header)?;
+    play.run(events)?;
+    Ok(render(&play))
+}
+
+// Play to the ending, restart and play again, the second run has to be the same as a fresh launch
+fn check_restart(events : &[ReplayEvent], header : &ReplayHeader, fresh : &str) -> Result<(), String> {
+    let mut play = playthrough(header)?;
+    play.run(events)?;
+    play.reset()?;
+    play.run(events)?;
+
+    match compare(fresh, &render(&play)) {
+        Some(e) => Err(format!("different after restarting, {}", e)),
+        None => Ok(())
+    }
+}
+
+// Play to the ending and go through GameState::Restart like the game does after it
+// The dialogue, cards and story have to be reset, and the runner has to be back at the start
+fn check_game_restart(events : &[ReplayEvent], header : &ReplayHeader) -> Result<(), String> {
+    let mut play = playthrough(header)?;
+    play.run(events)?;
+    let Playthrough { runner, lines, storage, state, .. } = play;
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+       .add_plugin(AssetPlugin::default())
+       .add_plugin(YarnPlugin)
+       .add_state::<GameState>()
+       .insert_resource(state)
+       .insert_resource(DialogueText::default())
+       .insert_resource(Replay::default())
+       .insert_resource(StoryState {
+           is_marco_here : true,
+           is_remie_here : false,
+           endings : HashSet::new(),
+           selected_options : HashMap::new(),
+           consequences : HashMap::new(),
+           current_question : 1,
+           the_end : true,
+           new_game_plus : true,
+       })
+       .add_systems(OnEnter(GameState::Restart), restart);
+
+    let runner = app.world.resource_mut::<Assets<YarnRunnerAsset>>().add(YarnRunnerAsset(runner));
+    let lines = app.world.resource_mut::<Assets<YarnLinesAsset>>().add(YarnLinesAsset(lines));
+    {
+        let mut yarn = app.world.resource_mut::<YarnManager>();
+        yarn.runner = Some(runner.clone());
+        yarn.lines = Some(lines);
+        yarn.storage = storage;
+        yarn.finished = true;
+    }
+    app.world.spawn(DialogueCard::default());
+    app.world.spawn(Drinks);
+
+    app.world.resource_mut::<NextState<GameState>>().set(GameState::Restart);
+    app.update();
+
+    // After an ending the restart goes back to the menu
+    if app.world.resource::<NextState<GameState>>().0 != Some(GameState::Menu) {
+        return Err("the restart after an ending doesn't go to the menu".to_string());
+    }
+
+    let story = app.world.resource::<StoryState>();
+    if story.the_end || story.new_game_plus || story.current_question != 0 || story.is_marco_here || !story.is_remie_here {
+        return Err("the story state was not reset by the restart".to_string());
+    }
+
+    let state = app.world.resource::<DialogueState>();
+    if !state.cards.is_empty() || !state.hand.is_empty() || !state.deck.is_empty() || !state.important_decision.is_empty() {
+        return Err("the cards were not reset by the restart".to_string());
+    }
+    if app.world.query::<&DialogueCard>().iter(&app.world).count() > 0 || app.world.query::<&Drinks>().iter(&app.world).count() > 0 {
+        return Err("the cards or drinks were not despawned by the restart".to_string());
+    }
+    if !app.world.resource::<DialogueText>().line().is_empty() {
+        return Err("the dialogue text was not reset by the restart".to_string());
+    }
+
+    // The first line has to be the same as in a fresh playthrough
+    let mut fresh = Playthrough::load("dialogue")?;
+    let expected = match fresh.runner.execute(&mut fresh.storage) {
+        Ok(Some(ExecutionOutput::Line(line))) => line.id,
+        _ => return Err("the script doesn't start with a line".to_string())
+    };
+    let yarn = app.world.resource::<YarnManager>();
+    if yarn.storage.iter().next().is_some() || yarn.finished || yarn.waiting_continue || yarn.waiting_response {
+        return Err("the yarn variables were not reset by the restart".to_string());
+    }
+    let found = match app.world.resource_mut::<Assets<YarnRunnerAsset>>().get_mut(&runner).map(|r| r.0.execute(&mut YarnStorage::new())) {
+        Some(Ok(Some(ExecutionOutput::Line(line)))) => line.id,
+        _ => return Err("the script doesn't start with a line after the restart".to_string())
+    };
+    if found != expected {
+        return Err(format!("the restart starts at {} instead of {}", found, expected));
+    }
+    Ok(())
+}
+
+// Show every line of the playthrough and read them with text to speech, like the game does
+// Each line has to be said once with its speaker, and nothing is said with the setting off
+fn check_speech(events : &[ReplayEvent], header : &ReplayHeader) -> Result<(), String> {
+    let mut play = playthrough(header)?;
+    play.run(events)?;
+
+    let spoken = Arc::new(Mutex::new(vec![]));
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+       .insert_resource(Settings { text_to_speech : true, ..default() })
+       .insert_resource(DialogueText::default())
+       .insert_resource(DialogueState::default())
+       .insert_resource(Speech(Box::new(RecordingBackend { spoken : spoken.clone() })))
+       .add_systems(Update, speech_update);
+
+    let mut expected = vec![];
+    for l in play.transcript.iter().filter(|l| !l.starts_with("> ") && !l.starts_with("<<")) {
+        let (voice, line) = match l.split_once(':') {
+            Some((v, t)) => (Some(v.to_string()), t.to_string()),
+            None => (None, l.clone())
+        };
+        let said = match &voice { Some(v) => format!("{}: {}", v, line), None => line.clone() };
+        let said = said.split_whitespace().collect::<Vec<_>>().join(" ");
+        if app.world.resource::<DialogueText>().line() != line && !said.is_empty() {
+            expected.push(said);
+        }
+
+        let mut text = app.world.resource_mut::<DialogueText>();
+        text.show(String::new(), TextStyle::default(), line);
+        text.voice = voice;
+        app.update();
+    }
+
+    app.world.resource_mut::<Settings>().text_to_speech = false;
+    app.world.resource_mut::<DialogueText>().show(String::new(), TextStyle::default(), "Not said".to_string());
+    app.update();
+
+    let spoken = spoken.lock().map_err(|_| "the speech log is poisoned".to_string())?;
+    match compare(&expected.join("\n"), &spoken.join("\n")) {
+        Some(e) => Err(format!("different text to speech, {}", e)),
+        None => Ok(())
+    }
+}
+
+// First difference between two transcripts
+fn compare(expected : &str, found : &str) -> Option<String> {
+    match expected.lines().zip(found.lines()).position(|(a, b)| a != b) {
+        Some(n) => Some(format!("line {} changed\n  - {}\n  + {}", n + 1, expected.lines().nth(n).unwrap(), found.lines().nth(n).unwrap())),
+        None if expected.lines().count() != found.lines().count() => Some(format!("expected {} lines, found {}", expected.lines().count(), found.lines().count())),
+        None => None
+    }
+}
+
+fn render(play : &Playthrough) -> String {
+    let mut out = play.transcript.clone();
+    out.push("---".to_string());
+
+    let mut vars : Vec<String> = play.storage.iter().map(|(k, v)| format!("{} = {}", k, v)).collect();
+    vars.sort();
+    out.extend(vars);
+
+    out.push(match &play.ending {
+        Some(n) => format!("ending {}", n),
+        None => "no ending".to_string()
+    });
+    out.join("\n") + "\n"
+}
+
+// Compare (or bless) every golden transcript in the folder
+// Returns the exit code, which is not zero if any of them changed
+pub fn run(dir : &str, bless : bool) -> i32 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        println!("Error, can't read the golden folder {}", dir);
+        return 1;
+    };
+    let mut paths : Vec<PathBuf> = entries.flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "choices" || e == "replay"))
+        .collect();
+    paths.sort();
+
+    let mut failed = 0;
+    for path in paths.iter() {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let golden = path.with_extension("transcript");
+
+        let data = fs::read_to_string(path).unwrap_or_default();
+        let header = ReplayHeader::read(data.lines());
+        let events = if path.extension().is_some_and(|e| e == "replay") { read_events(&data) } else { Ok(choice_events(&data)) };
+
+        let result = match events.and_then(|events| transcript(&events, &header).and_then(|t| check_restart(&events, &header, &t).and_then(|_| check_game_restart(&events, &header)).and_then(|_| check_speech(&events, &header)).map(|_| t))) {
+            Err(e) => Err(e),
+            Ok(t) if bless => fs::write(&golden, t).map_err(|e| e.to_string()),
+            Ok(t) => match fs::read_to_string(&golden) {
+                Err(_) => Err(format!("missing {}, run with --bless to create it", golden.display())),
+                Ok(g) => match compare(&g, &t) {
+                    Some(e) => Err(e),
+                    None => Ok(())
+                }
+            }
+        };
+
+        match result {
+            Ok(()) => println!("ok {}", name),
+            Err(e) => {
+                println!("FAILED {}: {}", name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{} golden transcripts, {} failed", paths.len(), failed);
+    if failed > 0 { 1 } else { 0 }
+}
@@ -0,0 +1,205 @@
+// Optional meters with the mood of Remie, enabled in the settings
+// Each meter mixes some yarn variables, defined in dialogue/meters.ron
+
+use super::{AssetsLoading, CamId, Props, settings::Settings, menu::text_style, yarn::YarnManager};
+use bevy::{
+    prelude::*,
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    utils::BoxedFuture,
+    reflect::TypeUuid
+};
+use serde::Deserialize;
+use yarn_spinner::YarnValue;
+use std::collections::HashMap;
+
+// ---
+// Constants
+
+const METER_BACKGROUND : Color = Color::rgba(0., 0., 0., 0.5);
+// Seconds the meters take to fill and to stop flashing after a change
+const METER_FILL_TIME : f32 = 0.6;
+const METER_FLASH_TIME : f32 = 1.2;
+
+// ---
+// Plugin
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<MetersAsset>()
+           .init_asset_loader::<MetersAssetLoader>()
+           .add_systems(Startup, meters_init)
+           .add_systems(Update, hud_update);
+    }
+}
+
+// ---
+// Resources
+
+#[derive(Deserialize)]
+pub struct Meter {
+    pub name : String,
+    // Weight of each yarn variable, booleans count as 0 or 1
+    pub variables : HashMap<String, f32>,
+    // Values shown as an empty and a full meter
+    pub range : (f32, f32),
+    pub color : (f32, f32, f32),
+}
+
+impl Meter {
+    // Fill of the meter, from 0 to 1
+    fn fill(&self, yarn : &YarnManager) -> f32 {
+        let value : f32 = self.variables.iter().map(|(name, weight)| weight * match yarn.storage.get(name) {
+            Some(YarnValue::F32(v)) => *v,
+            Some(YarnValue::Bool(true)) => 1.,
+            _ => 0.
+        }).sum();
+        ((value - self.range.0) / (self.range.1 - self.range.0)).clamp(0., 1.)
+    }
+
+    fn color(&self) -> Color {
+        Color::rgb(self.color.0, self.color.1, self.color.2)
+    }
+}
+
+#[derive(Resource)]
+struct Meters(Handle<MetersAsset>);
+
+// ---
+// Assets
+
+#[derive(TypeUuid, Deserialize)]
+#[uuid = "3f8b1d62-7e4c-4a95-b0d3-9c2e6a1f5d87"]
+pub struct MetersAsset(pub Vec<Meter>);
+
+#[derive(Default)]
+struct MetersAssetLoader;
+
+impl AssetLoader for MetersAssetLoader {
+    fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let meters = ron::de::from_bytes::<MetersAsset>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(meters));
+            Ok(())
+        })
+    }
+    fn extensions(&self) -> &[&str] { &["meters.ron"] }
+}
+
+// ---
+// Components
+
+#[derive(Component)]
+struct HudNode;
+
+// Bar of a meter, with the fill shown and the time since it last changed
+#[derive(Component)]
+struct MeterFill {
+    meter : usize,
+    shown : f32,
+    target : f32,
+    flash : f32,
+}
+
+// ---
+// Startup systems
+
+fn meters_init(mut cmd : Commands, assets : Res<AssetServer>, mut loading : ResMut<AssetsLoading>) {
+    let meters = assets.load("dialogue/meters.ron");
+    loading.0.push(meters.clone_untyped());
+    cmd.insert_resource(Meters(meters));
+}
+
+// ---
+// Update systems
+
+// Build the meters once they are loaded, and show them while playing if they are enabled
+fn hud_update(mut cmd : Commands,
+              time : Res<Time>,
+              props : Res<Props>,
+              settings : Res<Settings>,
+              yarn : Res<YarnManager>,
+              meters : Res<Meters>,
+              definitions : Res<Assets<MetersAsset>>,
+              cameras : Query<(&Camera, &CamId)>,
+              mut hud : Query<&mut Style, With<HudNode>>,
+              mut fills : Query<(&mut MeterFill, &mut Style, &mut BackgroundColor), Without<HudNode>>) {
+    let Some(MetersAsset(definitions)) = definitions.get(&meters.0) else { return; };
+
+    let Ok(mut hud) = hud.get_single_mut() else {
+        spawn_hud(&mut cmd, &props, definitions);
+        return;
+    };
+    let playing = cameras.iter().any(|(c, id)| *id == CamId::Player && c.is_active);
+    hud.display = if settings.mood_hud && playing { Display::Flex } else { Display::None };
+
+    let step = time.delta_seconds() / METER_FILL_TIME;
+    for (mut fill, mut style, mut color) in fills.iter_mut() {
+        let Some(meter) = definitions.get(fill.meter) else { continue; };
+
+        // Flash brighter when the value changes
+        let target = meter.fill(&yarn);
+        if target != fill.target {
+            fill.target = target;
+            fill.flash = METER_FLASH_TIME;
+        }
+        fill.shown += (fill.target - fill.shown).clamp(-step, step);
+        fill.flash = (fill.flash - time.delta_seconds()).max(0.);
+
+        style.size.width = Val::Percent(fill.shown * 100.);
+        let flash = 0.5 * fill.flash / METER_FLASH_TIME;
+        let (r, g, b) = meter.color;
+        color.0 = Color::rgb(r + (1. - r) * flash, g + (1. - g) * flash, b + (1. - b) * flash);
+    }
+}
+
+fn spawn_hud(cmd : &mut Commands, props : &Props, definitions : &[Meter]) {
+    cmd.spawn((
+        NodeBundle {
+            style : Style {
+                position_type : PositionType::Absolute,
+                position : UiRect { left : Val::Px(16.0), top : Val::Px(16.0), ..default() },
+                flex_direction : FlexDirection::Column,
+                gap : Size::new(Val::Auto, Val::Px(6.0)),
+                display : Display::None,
+                ..default()
+            },
+            ..default()
+        },
+        HudNode
+    ))
+    .with_children(|parent| {
+        for (i, meter) in definitions.iter().enumerate() {
+            parent.spawn(NodeBundle {
+                style : Style {
+                    align_items : AlignItems::Center,
+                    gap : Size::new(Val::Px(8.0), Val::Auto),
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|row| {
+                row.spawn(TextBundle::from_section(meter.name.as_str(), text_style(props, 16.0)).with_style(Style {
+                    size : Size::width(Val::Px(90.0)),
+                    ..default()
+                }));
+                row.spawn(NodeBundle {
+                    style : Style { size : Size::new(Val::Px(120.0), Val::Px(10.0)), ..default() },
+                    background_color : METER_BACKGROUND.into(),
+                    ..default()
+                })
+                .with_children(|bar| {
+                    bar.spawn((
+                        NodeBundle {
+                            style : Style { size : Size::new(Val::Percent(0.0), Val::Percent(100.0)), ..default() },
+                            background_color : meter.color().into(),
+                            ..default()
+                        },
+                        MeterFill { meter : i, shown : 0., target : 0., flash : 0. }
+                    ));
+                });
+            });
+        }
+    });
+}
@@ -1,46 +1,53 @@
 #![allow(clippy::too_many_arguments, clippy::type_complexity)]
 
 // TODO:
-// - Música
-// - Text 2 speech ally
-// - Luces cambian con ansiedad
 // - Expresiones faciales
 // - Mejorar menú
 // - Efectos especiales, polish, etc...
 
 mod yarn;
 mod dialogue;
+mod replay;
+mod golden;
+mod actions;
+mod menu;
+mod settings;
+mod endings;
+mod checkpoints;
+mod audio;
+mod speech;
+mod accessibility;
+mod content;
+mod mood;
+mod hud;
+mod tooltip;
 
 // ---
 
 use yarn::YarnPlugin;
-use yarn_spinner::YarnValue;
+use actions::ActionsPlugin;
+use settings::Settings;
+use endings::EndingsPlugin;
+use audio::SoundPlugin;
+use content::ContentPlugin;
+use mood::MoodPlugin;
+use hud::HudPlugin;
 
 use bevy::{
     prelude::*,
     window::WindowResolution,
-    render::{render_resource::TextureDescriptor, view::RenderLayers},
-    core_pipeline::clear_color::ClearColorConfig
+    render::render_resource::TextureDescriptor,
 };
 use bevy_pkv::PkvStore;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use noise::{Perlin, NoiseFn};
 
 // ---
 
-pub const NUM_ENDINGS : usize = 5;
-
-const MENU_BACKGROUND : Color = Color::rgb(0.05, 0.12, 0.08);
-const MENU_BUTTON_REGULAR : Color = Color::rgba(0., 0., 0., 0.2);
-const MENU_BUTTON_HOVER : Color = Color::rgba(0.2, 0.5, 0.3, 0.05);
-
 const LOOK_REMIE : Vec3 = Vec3::new(0.0, -0.2, -1.0);
 const LOOK_NICO : Vec3 = Vec3::new(0.2, -0.2, -1.0);
 
-const LIGHT_ANXIETY : Vec4 = Vec4::new(0.5, 0.5, 1.0, 1.0);
-const LIGHT_GOOD : Vec4 = Vec4::new(1.0, 0.7, 0.5, 1.0);
-
 // ---
 // App
 
@@ -48,12 +55,32 @@ fn main() {
     #[cfg(target_arch = "wasm32")]
     console_error_panic_hook::set_once();
 
+    // Check the golden transcripts without opening the game
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = golden::from_args(&args) {
+        std::process::exit(code);
+    }
+
+    // Text to speech backend, only used when it is enabled in the settings
+    let speech = speech::Speech::from_args(&args);
+
+    // Record or replay a playthrough, optionally without rendering
+    let replay = replay::Replay::from_args(args.into_iter());
+    if replay.headless {
+        std::process::exit(replay::run_headless(&replay));
+    }
+
+    // The settings are needed before creating the window
+    let storage = PersistentStorage(PkvStore::new("koala", "strawbevyjam"));
+    let settings = Settings::load(&storage);
+
     App::new()
         .add_plugins(DefaultPlugins
             .set(WindowPlugin {
                 primary_window: Some(Window {
                     title: "Strawbevy Jam".to_string(),
-                    resolution: WindowResolution::new(800., 800.),
+                    resolution: WindowResolution::new(settings.resolution.0, settings.resolution.1),
+                    mode: settings.window_mode,
                     ..default()
                 }),
                 ..default()
@@ -61,26 +88,67 @@ fn main() {
             .set(ImagePlugin::default_nearest())
         )
         .add_plugin(YarnPlugin)
-        .insert_resource(GameState::default())
+        .add_plugin(ActionsPlugin)
+        .add_plugin(EndingsPlugin)
+        .add_plugin(SoundPlugin)
+        .add_plugin(ContentPlugin)
+        .add_plugin(MoodPlugin)
+        .add_plugin(HudPlugin)
+        .add_event::<dialogue::DialogueCommand>()
+        .add_state::<GameState>()
         .insert_resource(AssetsLoading::default())
-        .insert_resource(PersistentStorage(PkvStore::new("koala", "strawbevyjam")))
+        .insert_resource(replay)
+        .insert_resource(storage)
+        .insert_resource(settings)
+        .insert_resource(speech)
+        .insert_resource(menu::MenuReturn::default())
+        .insert_resource(checkpoints::Checkpoints::default())
         .add_systems(PreStartup, (res_init, dialogue::res_init))
-        .add_systems(Startup, (menu_init, scene_init, dialogue::box_init))
+        .add_systems(Startup, (menu::menu_init, menu::pause_init, settings::settings_init, scene_init, dialogue::box_init, checkpoints::load_checkpoints, tooltip::tooltip_init))
+        .add_systems(OnEnter(GameState::Menu), (menu::enter_menu, endings::migrate_endings, menu::change_endings).chain())
+        .add_systems(OnExit(GameState::Menu), menu::exit_menu)
+        .add_systems(OnEnter(GameState::Pause), menu::enter_pause)
+        .add_systems(OnExit(GameState::Pause), menu::exit_pause)
+        .add_systems(OnEnter(GameState::Settings), settings::enter_settings)
+        .add_systems(OnExit(GameState::Settings), settings::exit_settings)
+        .add_systems(OnEnter(GameState::Gallery), endings::enter_gallery)
+        .add_systems(OnExit(GameState::Gallery), endings::exit_gallery)
+        .add_systems(OnEnter(GameState::Chapters), checkpoints::enter_chapters)
+        .add_systems(OnExit(GameState::Chapters), checkpoints::exit_chapters)
+        .add_systems(OnEnter(GameState::Content), content::enter_content)
+        .add_systems(OnExit(GameState::Content), content::exit_content)
+        .add_systems(OnEnter(GameState::Play), (enter_play, checkpoints::restore_checkpoint))
+        .add_systems(OnEnter(GameState::Restart), restart)
         .add_systems(Update, (
-            change_cam
-                .run_if(resource_changed::<GameState>()),
-            change_endings
+            menu::change_endings
                 .run_if(resource_changed::<StoryState>()),
+            settings::apply_settings
+                .run_if(resource_changed::<Settings>()),
+            dialogue::release_cards_update,
+            accessibility::menu_access_update
+                .run_if(state_changed::<GameState>()),
+            tooltip::tooltip_update,
+            dialogue::card_debug_update
+                .run_if(|| cfg!(debug_assertions)),
             check_loading
-                .run_if(resource_exists::<GameState>().and_then(|state : Res<GameState>| matches!(*state, GameState::Loading) )),
-            menu_update
-                .run_if(resource_exists::<GameState>().and_then(|state : Res<GameState>| matches!(*state, GameState::Menu) )),
-            (dialogue::update, dialogue::card_update, dialogue::pick_card_update,
+                .run_if(in_state(GameState::Loading)),
+            menu::menu_update
+                .run_if(in_state(GameState::Menu).or_else(in_state(GameState::Pause)).or_else(in_state(GameState::Settings)).or_else(in_state(GameState::Gallery)).or_else(in_state(GameState::Chapters)).or_else(in_state(GameState::Content))),
+            menu::pause_update
+                .run_if(in_state(GameState::Pause)),
+            settings::settings_update
+                .run_if(in_state(GameState::Settings)),
+            checkpoints::chapters_update
+                .run_if(in_state(GameState::Chapters)),
+            content::content_update
+                .run_if(in_state(GameState::Content)),
+            checkpoints::save_checkpoints
+                .run_if(resource_changed::<checkpoints::Checkpoints>()),
+            (dialogue::update, dialogue::text_update, dialogue::card_update, dialogue::pick_card_update,
              dialogue::create_cards_update, dialogue::card_words_update,
-             candle_update, character_update, player_update, transparency_update, check_for_menu_update)
-                .run_if(resource_exists::<GameState>().and_then(|state : Res<GameState>| matches!(*state, GameState::Play) )),
-            restart
-                .run_if(resource_exists::<GameState>().and_then(|state : Res<GameState>| matches!(*state, GameState::Restart) )),
+             candle_update, character_update, player_update, transparency_update, menu::check_for_pause_update,
+             speech::speech_update, accessibility::line_access_update, accessibility::card_access_update)
+                .run_if(in_state(GameState::Play)),
         ))
         .run();
 }
@@ -97,32 +165,26 @@ enum Character {
     Marco
 }
 
-#[derive(Component)]
-enum CamId {
+#[derive(Component, PartialEq)]
+pub enum CamId {
     Player,
     Menu
 }
 
-#[derive(Component)]
-enum MenuButton {
-    Start,
-}
-
-#[derive(Component)]
-struct MenuNode;
-
-#[derive(Component)]
-struct MenuEndings;
-
 // ---
 // Resources
 
-#[derive(Resource, Default)]
+#[derive(States, Default, Debug, Clone, Eq, PartialEq, Hash)]
 pub enum GameState{
     #[default]
     Loading,
     Menu,
     Play,
+    Pause,
+    Settings,
+    Gallery,
+    Chapters,
+    Content,
     Restart,
 }
 
@@ -130,10 +192,36 @@ pub enum GameState{
 pub struct StoryState{
     is_marco_here : bool,
     is_remie_here : bool,
-    endings : [bool; NUM_ENDINGS],
+    endings : HashSet<String>,
     selected_options : HashMap<u64, Vec<String>>,
+    // What happened the last time each card was played for a question
+    consequences : HashMap<u64, HashMap<String, String>>,
     current_question : u64,
     the_end : bool,
+    // The script remembers previous runs, unlocked after the first ending
+    new_game_plus : bool,
+}
+
+impl StoryState {
+    // Reset the state of a run, keeping what is remembered between runs
+    fn new_run(&mut self) {
+        // A finished run goes back to the regular mode
+        if self.the_end {
+            self.new_game_plus = false;
+        }
+        self.is_marco_here = false;
+        self.is_remie_here = true;
+        self.current_question = 0;
+        self.the_end = false;
+    }
+
+    // What the script remembers from previous runs, only in New Game+
+    fn memory(&self) -> Option<dialogue::RunMemory> {
+        self.new_game_plus.then(|| dialogue::RunMemory {
+            endings : self.endings.clone(),
+            chosen : self.selected_options.values().flatten().cloned().collect(),
+        })
+    }
 }
 
 #[derive(Resource)]
@@ -162,19 +250,25 @@ pub struct PersistentStorage(PkvStore);
 // Startup systems
 
 // Resource initialization
-fn res_init(mut cmd : Commands, storage : Res<PersistentStorage>) {
+fn res_init(mut cmd : Commands, assets : Res<AssetServer>, mut loading : ResMut<AssetsLoading>, storage : Res<PersistentStorage>) {
     // Perlin noise resource
     cmd.insert_resource(PerlinNoise(Perlin::new(1)));
 
     // Persistent storage
-    let mut endings : [bool; NUM_ENDINGS] = [false; NUM_ENDINGS];
+    let mut endings : HashSet<String> = HashSet::new();
     let mut selected_options : HashMap<u64, Vec<String>> = HashMap::new();
-    if let Ok(unlocked) = storage.0.get::<[bool; NUM_ENDINGS]>("unlocked_endings") {
+    if let Ok(unlocked) = storage.0.get::<HashSet<String>>("endings") {
         endings = unlocked;
     }
     if let Ok(options) = storage.0.get::<HashMap<u64, Vec<String>>>("selected_options") {
         selected_options = options;
     }
+    let consequences = storage.0.get::<HashMap<u64, HashMap<String, String>>>("consequences").unwrap_or_default();
+
+    // Ending definitions
+    let definitions = assets.load("dialogue/dialogue.endings.ron");
+    loading.0.push(definitions.clone_untyped());
+    cmd.insert_resource(endings::Endings(definitions));
 
     // Story state
     cmd.insert_resource(StoryState{
@@ -182,8 +276,10 @@ fn res_init(mut cmd : Commands, storage : Res<PersistentStorage>) {
         is_remie_here : true,
         endings,
         selected_options,
+        consequences,
         current_question : 0,
-        the_end : false
+        the_end : false,
+        new_game_plus : false,
     });
 
     // Player
@@ -196,88 +292,6 @@ fn res_init(mut cmd : Commands, storage : Res<PersistentStorage>) {
     ));
 }
 
-// Menu initialization
-fn menu_init(mut cmd : Commands, props : Res<Props>) {
-    // Menú camera
-    let menu_pass_layer = RenderLayers::layer(1);
-    cmd.spawn((
-        Camera2dBundle {
-            camera_2d: Camera2d {
-                clear_color: ClearColorConfig::Custom(MENU_BACKGROUND)
-            },
-            camera : Camera {
-                is_active : false,
-                ..default()
-            },
-            ..default()
-        },
-        menu_pass_layer,
-        CamId::Menu
-    ));
-
-    // Menu text style
-    let button_style = TextStyle {
-        font: props.font.clone(),
-        font_size: 24.0,
-        color: Color::rgb(0.9, 0.9, 0.9),
-    };
-    let title_style = TextStyle {
-        font: props.font.clone(),
-        font_size: 48.0,
-        color: Color::rgb(0.9, 0.9, 0.9),
-    };
-    let small_style = TextStyle {
-        font: props.font.clone(),
-        font_size: 16.0,
-        color: Color::rgb(0.9, 0.9, 0.9),
-    };
-
-    // Menu node
-    cmd.spawn((
-        NodeBundle {
-            style : Style {
-                size : Size::width(Val::Percent(100.0)),
-                align_items : AlignItems::Center,
-                justify_content : JustifyContent::Center,
-                flex_direction: FlexDirection::Column,
-                gap : Size::new(Val::Auto, Val::Px(48.0)),
-                ..default()
-            },
-            ..default()
-        },
-        menu_pass_layer,
-        MenuNode
-    ))
-    .with_children(|parent| {
-        parent.spawn(
-            TextBundle::from_section("We don't talk\nabout Remie", title_style).with_text_alignment(TextAlignment::Center)
-        );
-
-        let button_flex_style = Style {
-            size : Size::new(Val::Px(150.0), Val::Px(65.0)),
-            justify_content : JustifyContent::Center,
-            align_items : AlignItems::Center,
-            ..default()
-        };
-
-        parent.spawn((
-            ButtonBundle {
-                style : button_flex_style,
-                background_color : MENU_BUTTON_REGULAR.into(),
-                ..default()
-            },
-            MenuButton::Start
-        )).with_children(|parent| {
-            parent.spawn(TextBundle::from_section("Play", button_style.clone()));
-        });
-
-        parent.spawn((
-            TextBundle::from_section(format!("Discovered 0/{} endings", NUM_ENDINGS), small_style),
-            MenuEndings{}
-        ));
-    });
-}
-
 // 3D Scene initalization
 fn scene_init(mut cmd : Commands,
               assets : Res<AssetServer>,
@@ -348,84 +362,44 @@ fn scene_init(mut cmd : Commands,
 // Load systems
 
 // Check if loading is finished
-fn check_loading(mut cmd : Commands, mut state : ResMut<GameState>, assets : Res<AssetServer>, loading : Res<AssetsLoading>) {
+fn check_loading(mut cmd : Commands, mut state : ResMut<NextState<GameState>>, assets : Res<AssetServer>, loading : Res<AssetsLoading>) {
     use bevy::asset::LoadState;
     match assets.get_group_load_state(loading.0.iter().map(|x| x.id())) {
         LoadState::Failed => todo!(),
         LoadState::Loaded => {
             cmd.remove_resource::<AssetsLoading>();
-            *state = GameState::Menu;
+            state.set(GameState::Menu);
         },
         _ => ()
     }
 }
 
-// Change the active camera (menu / player)
-fn change_cam(state : Res<GameState>,
-              mut cameras : Query<(&mut Camera, &CamId)>,
-              mut node : Query<&mut Visibility, With<MenuNode>>) {
+// ---
+// State transitions
+
+// Activate or deactivate one of the cameras (menu / player)
+pub fn set_cam(cameras : &mut Query<(&mut Camera, &CamId)>, id : CamId, active : bool) {
     for (mut cam, cam_id) in cameras.iter_mut() {
-        match cam_id {
-            CamId::Player => cam.is_active = matches!(*state, GameState::Play),
-            CamId::Menu => cam.is_active = matches!(*state, GameState::Menu),
+        if *cam_id == id {
+            cam.is_active = active;
         }
     }
-    node.iter_mut().for_each(|mut x| *x = match *state {
-        GameState::Menu => Visibility::Visible,
-        _ => Visibility::Hidden
-    })
 }
 
-// Update the endings in the menu
-fn change_endings(story : Res<StoryState>, 
-                  mut state : ResMut<GameState>,
-                  mut text : Query<&mut Text, With<MenuEndings>>) {
-    if let Ok(mut text) = text.get_single_mut() {
-        let endings = story.endings.iter().filter(|x| **x).count();
-        text.sections[0].value = format!("Discovered {}/{} endings", endings, NUM_ENDINGS);
-    }
-
-    if story.the_end {
-        *state = GameState::Restart;
-    }
+fn enter_play(mut time : ResMut<Time>, mut cameras : Query<(&mut Camera, &CamId)>) {
+    time.unpause();
+    set_cam(&mut cameras, CamId::Menu, false);
+    set_cam(&mut cameras, CamId::Player, true);
 }
 
 // ---
 // Update systems
 
-// Main menu
-fn check_for_menu_update(mut state : ResMut<GameState>,
-                         keyboard : Res<Input<KeyCode>>) {
-    if keyboard.just_pressed(KeyCode::Escape) {
-        *state = GameState::Menu;
-    }
-}
-
-fn menu_update(mut state : ResMut<GameState>,
-               mut buttons : Query<(&Interaction, &mut BackgroundColor, &MenuButton), Changed<Interaction>>) {
-    for (inter, mut bg, button) in buttons.iter_mut() {
-        match *inter {
-            Interaction::Clicked => {
-                match *button {
-                    MenuButton::Start => {
-                        *state = GameState::Play;
-                    },
-                }
-            },
-            Interaction::Hovered => {
-                *bg = MENU_BUTTON_HOVER.into();
-            },
-            Interaction::None => {
-                *bg = MENU_BUTTON_REGULAR.into();
-            },
-        }
-    }
-}
-
 // Animate Characters
-fn character_update(time : Res<Time>, story : Res<StoryState>, mut characters : Query<(&mut Transform, &mut Visibility, &Character)>) {
+fn character_update(time : Res<Time>, story : Res<StoryState>, settings : Res<Settings>, mut characters : Query<(&mut Transform, &mut Visibility, &Character)>) {
+    let bob = if settings.reduced_motion { 0. } else { 0.05 };
     for (mut trans, mut visible, character) in characters.iter_mut() {
-        trans.translation.y = 3.5 + (time.elapsed_seconds() * if let Character::Remie = character {1.5} else {1.8}).cos() * 0.05;
+        trans.translation.y = 3.5 + (time.elapsed_seconds() * if let Character::Remie = character {1.5} else {1.8}).cos() * bob;
         *visible = match character {
             Character::Remie => if story.is_remie_here { Visibility::Visible } else { Visibility::Hidden },
             Character::Marco => if story.is_marco_here { Visibility::Visible } else { Visibility::Hidden },
@@ -444,6 +418,7 @@ pub fn smoothstep(x : f32, a : f32, b : f32) -> f32 {
 // Animate player camera
 fn player_update(time : Res<Time>,
                  story : Res<StoryState>,
+                 settings : Res<Settings>,
                  mut player : Query<&mut Transform, With<Player>>,
                  mut lerp_time : Local<(f32, bool)>) {
     if let Ok(mut trans) = player.get_single_mut() {
@@ -451,7 +426,9 @@ fn player_update(time : Res<Time>,
             *lerp_time = (0., story.is_marco_here);
         }
 
-        let head_wobble = Vec3::new(if lerp_time.0 >= 1. { time.elapsed_seconds().sin() * 0.005 } else { 0. }, 0., 0.);
+        // Reduced motion turns instantly and keeps the head still
+        if settings.reduced_motion { lerp_time.0 = 1.; }
+        let head_wobble = Vec3::new(if lerp_time.0 >= 1. && !settings.reduced_motion { time.elapsed_seconds().sin() * 0.005 } else { 0. }, 0., 0.);
 
         if story.is_marco_here {
             *trans = trans.looking_at(trans.translation + head_wobble + LOOK_REMIE.lerp(LOOK_NICO, smoothstep(lerp_time.0, 0., 1.)), Vec3::Y);
@@ -464,18 +441,13 @@ fn player_update(time : Res<Time>,
     }
 }
 
-// Also animate the candle lighs with flicker
-fn candle_update(time : Res<Time>, perlin : Res<PerlinNoise>, mut lights : Query<&mut PointLight>, yarn : Res<yarn::YarnManager>) {
+// Also animate the candle lighs with flicker, with the color and brightness of the mood
+fn candle_update(time : Res<Time>, perlin : Res<PerlinNoise>, mut lights : Query<&mut PointLight>, mood : Res<mood::Mood>) {
     for mut light in lights.iter_mut() {
         if light.intensity < 800. {
-            light.intensity = 200. + 40. * perlin.0.get([3. * time.elapsed_seconds() as f64, 0.0]) as f32;
-        }
-        if yarn.storage.contains_key("$anxiety") {
-            let anxiety = yarn.storage.get("$anxiety").unwrap();
-            if let YarnValue::F32(anxiety) = anxiety {
-                light.color = Color::from(LIGHT_GOOD.lerp(LIGHT_ANXIETY, *anxiety * 0.1))
-            }
+            light.intensity = (200. + 40. * perlin.0.get([3. * time.elapsed_seconds() as f64, 0.0]) as f32) * mood.current.intensity;
         }
+        light.color = mood.light();
     }
 }
 
@@ -490,34 +462,31 @@ fn transparency_update(mut materials : ResMut<Assets<StandardMaterial>>, mut don
 }
 
 fn restart(mut cmd : Commands,
-           mut state : ResMut<GameState>,
+           mut state : ResMut<NextState<GameState>>,
            mut story : ResMut<StoryState>,
            mut dialogue_state : ResMut<dialogue::DialogueState>,
+           mut text : ResMut<dialogue::DialogueText>,
            mut yarn : ResMut<yarn::YarnManager>,
+           mut replay : ResMut<replay::Replay>,
            asset_lines : Res<Assets<yarn::YarnLinesAsset>>,
            mut asset_runner : ResMut<Assets<yarn::YarnRunnerAsset>>,
            drinks : Query<(Entity, Option<&dialogue::Drinks>, Option<&dialogue::DialogueCard>)>) {
-    story.is_marco_here = false;
-    story.is_remie_here = true;
-    story.the_end = false;
-    yarn.finished = false;
-    
-    *dialogue_state = dialogue::DialogueState {
-        selected_card : None,
-        previous_card : None,
-        cards : HashMap::new(),
-        ..default()
-    };
+    // Go back to the menu after an ending, or start again when restarting from the pause menu
+    state.set(if story.the_end { GameState::Menu } else { GameState::Play });
 
+    story.new_run();
+    replay.start_run(story.memory());
+    yarn.reset();
+    *text = dialogue::DialogueText::default();
+
+    // The card renderers are released when the cards are despawned
     drinks.iter().for_each(|(x, d, c)| if d.is_some() || c.is_some() { cmd.entity(x).despawn(); });
 
     let (runner, _) = match yarn::get_yarn_components(&yarn, &mut asset_runner, &asset_lines) {
         None => return,
         Some(v) => v
     };
-    if runner.set_node("Start").is_err() {
+    if !dialogue::reset_run(runner, &mut yarn.storage, &mut dialogue_state) {
         println!("Warning, can't restart");
     }
-
-    *state = GameState::Menu;
 }
\ No newline at end of file
@@ -0,0 +1,287 @@
+// Main menu and pause overlay
+
+use super::{CamId, GameState, Props, StoryState, set_cam, accessibility, actions::{Actions, Action}, endings::{Endings, EndingsAsset}};
+use bevy::{
+    prelude::*,
+    render::view::RenderLayers,
+    core_pipeline::clear_color::ClearColorConfig,
+    a11y::accesskit::Role
+};
+
+// ---
+// Constants
+
+const MENU_BACKGROUND : Color = Color::rgb(0.05, 0.12, 0.08);
+pub const MENU_BUTTON_REGULAR : Color = Color::rgba(0., 0., 0., 0.2);
+pub const MENU_BUTTON_HOVER : Color = Color::rgba(0.2, 0.5, 0.3, 0.05);
+
+const PAUSE_BACKGROUND : Color = Color::rgba(0.02, 0.05, 0.03, 0.8);
+
+// ---
+// Resources
+
+// State to go back to when leaving the settings or the endings gallery
+#[derive(Resource, Default)]
+pub struct MenuReturn(pub GameState);
+
+// ---
+// Components
+
+#[derive(Component)]
+pub enum MenuButton {
+    Start,
+    NewGamePlus,
+    Resume,
+    // Opens the settings, going back to this state when leaving
+    Settings(GameState),
+    Content(GameState),
+    Endings,
+    Chapters,
+    Back,
+    Restart,
+    Quit,
+}
+
+#[derive(Component)]
+pub struct MenuNode;
+
+#[derive(Component)]
+pub struct PauseNode;
+
+#[derive(Component)]
+pub struct MenuEndings;
+
+// ---
+// Startup systems
+
+pub fn text_style(props : &Props, font_size : f32) -> TextStyle {
+    TextStyle {
+        font: props.font.clone(),
+        font_size,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    }
+}
+
+pub fn spawn_button(parent : &mut ChildBuilder, text : &str, style : TextStyle, button : MenuButton) {
+    let button_flex_style = Style {
+        size : Size::new(Val::Px(150.0), Val::Px(65.0)),
+        justify_content : JustifyContent::Center,
+        align_items : AlignItems::Center,
+        ..default()
+    };
+
+    parent.spawn((
+        ButtonBundle {
+            style : button_flex_style,
+            background_color : MENU_BUTTON_REGULAR.into(),
+            ..default()
+        },
+        button,
+        accessibility::label(Role::Button, text)
+    )).with_children(|parent| {
+        parent.spawn(TextBundle::from_section(text, style));
+    });
+}
+
+// Menu initialization
+pub fn menu_init(mut cmd : Commands, props : Res<Props>) {
+    // Menú camera
+    let menu_pass_layer = RenderLayers::layer(1);
+    cmd.spawn((
+        Camera2dBundle {
+            camera_2d: Camera2d {
+                clear_color: ClearColorConfig::Custom(MENU_BACKGROUND)
+            },
+            camera : Camera {
+                is_active : false,
+                ..default()
+            },
+            ..default()
+        },
+        menu_pass_layer,
+        CamId::Menu
+    ));
+
+    // Menu node
+    cmd.spawn((
+        NodeBundle {
+            style : Style {
+                size : Size::width(Val::Percent(100.0)),
+                align_items : AlignItems::Center,
+                justify_content : JustifyContent::Center,
+                flex_direction: FlexDirection::Column,
+                gap : Size::new(Val::Auto, Val::Px(48.0)),
+                ..default()
+            },
+            visibility : Visibility::Hidden,
+            ..default()
+        },
+        menu_pass_layer,
+        MenuNode
+    ))
+    .with_children(|parent| {
+        parent.spawn(
+            TextBundle::from_section("We don't talk\nabout Remie", text_style(&props, 48.0)).with_text_alignment(TextAlignment::Center)
+        );
+
+        spawn_button(parent, "Play", text_style(&props, 24.0), MenuButton::Start);
+        spawn_button(parent, "New Game+", text_style(&props, 24.0), MenuButton::NewGamePlus);
+        spawn_button(parent, "Settings", text_style(&props, 24.0), MenuButton::Settings(GameState::Menu));
+        spawn_button(parent, "Content", text_style(&props, 24.0), MenuButton::Content(GameState::Menu));
+        spawn_button(parent, "Chapters", text_style(&props, 24.0), MenuButton::Chapters);
+        spawn_button(parent, "Endings", text_style(&props, 24.0), MenuButton::Endings);
+
+        parent.spawn((
+            TextBundle::from_section("", text_style(&props, 16.0)),
+            MenuEndings{}
+        ));
+    });
+}
+
+// Pause overlay, drawn on top of the scene
+pub fn pause_init(mut cmd : Commands, props : Res<Props>) {
+    cmd.spawn((
+        NodeBundle {
+            style : Style {
+                size : Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                align_items : AlignItems::Center,
+                justify_content : JustifyContent::Center,
+                flex_direction: FlexDirection::Column,
+                gap : Size::new(Val::Auto, Val::Px(24.0)),
+                ..default()
+            },
+            background_color : PAUSE_BACKGROUND.into(),
+            visibility : Visibility::Hidden,
+            ..default()
+        },
+        PauseNode
+    ))
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section("Paused", text_style(&props, 48.0)));
+
+        spawn_button(parent, "Resume", text_style(&props, 24.0), MenuButton::Resume);
+        spawn_button(parent, "Settings", text_style(&props, 24.0), MenuButton::Settings(GameState::Pause));
+        spawn_button(parent, "Content", text_style(&props, 24.0), MenuButton::Content(GameState::Pause));
+        spawn_button(parent, "Restart", text_style(&props, 24.0), MenuButton::Restart);
+        spawn_button(parent, "Quit", text_style(&props, 24.0), MenuButton::Quit);
+    });
+}
+
+// ---
+// State transitions
+
+pub fn enter_menu(mut time : ResMut<Time>,
+                  mut cameras : Query<(&mut Camera, &CamId)>,
+                  mut node : Query<&mut Visibility, With<MenuNode>>) {
+    time.unpause();
+    set_cam(&mut cameras, CamId::Player, false);
+    set_cam(&mut cameras, CamId::Menu, true);
+    node.iter_mut().for_each(|mut x| *x = Visibility::Visible);
+}
+
+// The menu camera stays on so the settings can be drawn on top of it
+pub fn exit_menu(mut node : Query<&mut Visibility, With<MenuNode>>) {
+    node.iter_mut().for_each(|mut x| *x = Visibility::Hidden);
+}
+
+// Stop the game clock so timers and animations freeze while paused
+// It keeps stopped in the settings and starts again when playing or going back to the menu
+pub fn enter_pause(mut time : ResMut<Time>,
+                   mut node : Query<&mut Visibility, With<PauseNode>>) {
+    time.pause();
+    node.iter_mut().for_each(|mut x| *x = Visibility::Visible);
+}
+
+pub fn exit_pause(mut node : Query<&mut Visibility, With<PauseNode>>) {
+    node.iter_mut().for_each(|mut x| *x = Visibility::Hidden);
+}
+
+// ---
+// Update systems
+
+// Update the endings in the menu
+pub fn change_endings(story : Res<StoryState>,
+                      endings : Res<Endings>,
+                      definitions : Res<Assets<EndingsAsset>>,
+                      mut text : Query<&mut Text, With<MenuEndings>>,
+                      mut buttons : Query<(&MenuButton, &mut Style)>) {
+    // New Game+ is only available after reaching an ending
+    for (button, mut style) in buttons.iter_mut() {
+        if let MenuButton::NewGamePlus = button {
+            style.display = if story.endings.is_empty() { Display::None } else { Display::Flex };
+        }
+    }
+
+    if let (Ok(mut text), Some(EndingsAsset(definitions))) = (text.get_single_mut(), definitions.get(&endings.0)) {
+        let unlocked = definitions.iter().filter(|e| story.endings.contains(&e.id)).count();
+        text.sections[0].value = format!("Discovered {}/{} endings", unlocked, definitions.len());
+    }
+}
+
+// Pause the game
+pub fn check_for_pause_update(mut state : ResMut<NextState<GameState>>,
+                              actions : Res<Actions>) {
+    if actions.just_pressed(Action::Menu) {
+        state.set(GameState::Pause);
+    }
+}
+
+// Resume the game
+pub fn pause_update(mut state : ResMut<NextState<GameState>>,
+                    actions : Res<Actions>) {
+    if actions.just_pressed(Action::Menu) {
+        state.set(GameState::Play);
+    }
+}
+
+pub fn menu_update(mut state : ResMut<NextState<GameState>>,
+                   mut story : ResMut<StoryState>,
+                   mut menu_return : ResMut<MenuReturn>,
+                   mut buttons : Query<(&Interaction, &mut BackgroundColor, &MenuButton), Changed<Interaction>>) {
+    for (inter, mut bg, button) in buttons.iter_mut() {
+        match *inter {
+            Interaction::Clicked => {
+                match *button {
+                    MenuButton::Start | MenuButton::Resume => {
+                        state.set(GameState::Play);
+                    },
+                    MenuButton::NewGamePlus => {
+                        story.new_game_plus = true;
+                        state.set(GameState::Restart);
+                    },
+                    MenuButton::Settings(ref from) => {
+                        menu_return.0 = from.clone();
+                        state.set(GameState::Settings);
+                    },
+                    MenuButton::Content(ref from) => {
+                        menu_return.0 = from.clone();
+                        state.set(GameState::Content);
+                    },
+                    MenuButton::Endings => {
+                        menu_return.0 = GameState::Menu;
+                        state.set(GameState::Gallery);
+                    },
+                    MenuButton::Chapters => {
+                        menu_return.0 = GameState::Menu;
+                        state.set(GameState::Chapters);
+                    },
+                    MenuButton::Back => {
+                        state.set(menu_return.0.clone());
+                    },
+                    MenuButton::Restart => {
+                        state.set(GameState::Restart);
+                    },
+                    MenuButton::Quit => {
+                        state.set(GameState::Menu);
+                    },
+                }
+            },
+            Interaction::Hovered => {
+                *bg = MENU_BUTTON_HOVER.into();
+            },
+            Interaction::None => {
+                *bg = MENU_BUTTON_REGULAR.into();
+            },
+        }
+    }
+}
@@ -0,0 +1,223 @@
+// Mood of the scene, the lights, fog, vignette and camera shake follow the tension of the conversation
+// The tension mixes $anxiety and $offense, and the curve from dialogue/mood.ron maps it to each effect
+
+use super::{AssetsLoading, CamId, PerlinNoise, smoothstep, settings::Settings, yarn::YarnManager};
+use bevy::{
+    prelude::*,
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    utils::BoxedFuture,
+    reflect::TypeUuid,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use noise::NoiseFn;
+use serde::Deserialize;
+use yarn_spinner::YarnValue;
+
+// ---
+// Constants
+
+// Seconds to get most of the way to a new mood
+const MOOD_FADE_TIME : f32 = 1.5;
+// Distance between the start and the end of the fog
+const FOG_DEPTH : f32 = 15.;
+const VIGNETTE_SIZE : u32 = 128;
+const SHAKE_SPEED : f64 = 6.;
+
+// ---
+// Plugin
+
+pub struct MoodPlugin;
+
+impl Plugin for MoodPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<MoodAsset>()
+           .init_asset_loader::<MoodAssetLoader>()
+           .insert_resource(Mood::default())
+           .add_systems(Startup, mood_init)
+           .add_systems(Update, (mood_update, (fog_update, vignette_update, shake_update)).chain());
+    }
+}
+
+// ---
+// Resources
+
+// Every effect of the mood at one point of the curve
+#[derive(Deserialize, Clone, Copy)]
+pub struct MoodKey {
+    pub tension : f32,
+    pub light : (f32, f32, f32),
+    pub intensity : f32,
+    pub fog : f32,
+    pub vignette : f32,
+    pub shake : f32,
+}
+
+impl Default for MoodKey {
+    fn default() -> Self {
+        MoodKey { tension : 0., light : (1.0, 0.7, 0.5), intensity : 1., fog : 35., vignette : 0., shake : 0. }
+    }
+}
+
+impl MoodKey {
+    fn lerp(&self, other : &MoodKey, t : f32) -> MoodKey {
+        let mix = |a : f32, b : f32| a + (b - a) * t;
+        MoodKey {
+            tension : mix(self.tension, other.tension),
+            light : (mix(self.light.0, other.light.0), mix(self.light.1, other.light.1), mix(self.light.2, other.light.2)),
+            intensity : mix(self.intensity, other.intensity),
+            fog : mix(self.fog, other.fog),
+            vignette : mix(self.vignette, other.vignette),
+            shake : mix(self.shake, other.shake),
+        }
+    }
+}
+
+// Current mood, eased towards the one of the story
+#[derive(Resource, Default)]
+pub struct Mood {
+    pub current : MoodKey,
+    definition : Handle<MoodAsset>,
+}
+
+impl Mood {
+    pub fn light(&self) -> Color {
+        Color::rgb(self.current.light.0, self.current.light.1, self.current.light.2)
+    }
+}
+
+// ---
+// Assets
+
+#[derive(TypeUuid, Deserialize)]
+#[uuid = "a7c3e915-2d48-4b6f-8e0a-5f1b9d3c7e24"]
+pub struct MoodAsset {
+    // Weight of each variable in the tension
+    anxiety : f32,
+    offense : f32,
+    // Points of the curve, sorted by tension
+    keys : Vec<MoodKey>,
+}
+
+impl MoodAsset {
+    fn tension(&self, yarn : &YarnManager) -> f32 {
+        let var = |name : &str| match yarn.storage.get(name) {
+            Some(YarnValue::F32(v)) => *v,
+            _ => 0.
+        };
+        var("$anxiety") * self.anxiety + var("$offense") * self.offense
+    }
+
+    // Mood at a point of the curve, holding the first and last keys outside of it
+    fn sample(&self, tension : f32) -> MoodKey {
+        let (Some(first), Some(last)) = (self.keys.first(), self.keys.last()) else { return MoodKey::default(); };
+        if tension <= first.tension { return *first; }
+        match self.keys.windows(2).find(|w| tension < w[1].tension) {
+            Some(w) => w[0].lerp(&w[1], smoothstep(tension, w[0].tension, w[1].tension)),
+            None => *last
+        }
+    }
+}
+
+#[derive(Default)]
+struct MoodAssetLoader;
+
+impl AssetLoader for MoodAssetLoader {
+    fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let mood = ron::de::from_bytes::<MoodAsset>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(mood));
+            Ok(())
+        })
+    }
+    fn extensions(&self) -> &[&str] { &["mood.ron"] }
+}
+
+// ---
+// Components
+
+#[derive(Component)]
+struct Vignette;
+
+// ---
+// Startup systems
+
+fn mood_init(mut cmd : Commands,
+             assets : Res<AssetServer>,
+             mut mood : ResMut<Mood>,
+             mut loading : ResMut<AssetsLoading>,
+             mut images : ResMut<Assets<Image>>) {
+    mood.definition = assets.load("dialogue/mood.ron");
+    loading.0.push(mood.definition.clone_untyped());
+
+    // Black texture that gets more opaque towards the borders
+    let half = VIGNETTE_SIZE as f32 / 2.;
+    let data = (0..VIGNETTE_SIZE * VIGNETTE_SIZE).flat_map(|i| {
+        let (x, y) = ((i % VIGNETTE_SIZE) as f32 - half, (i / VIGNETTE_SIZE) as f32 - half);
+        let alpha = smoothstep(Vec2::new(x, y).length() / half, 0.5, 1.3);
+        [0, 0, 0, (alpha * 255.) as u8]
+    }).collect();
+    let image = Image::new(
+        Extent3d { width : VIGNETTE_SIZE, height : VIGNETTE_SIZE, depth_or_array_layers : 1 },
+        TextureDimension::D2, data, TextureFormat::Rgba8UnormSrgb
+    );
+
+    cmd.spawn((
+        ImageBundle {
+            style : Style {
+                size : Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type : PositionType::Absolute,
+                ..default()
+            },
+            image : images.add(image).into(),
+            background_color : Color::rgba(1., 1., 1., 0.).into(),
+            z_index : ZIndex::Global(-1),
+            ..default()
+        },
+        Vignette
+    ));
+}
+
+// ---
+// Update systems
+
+// Ease the mood towards the tension of the story
+fn mood_update(time : Res<Time>,
+               yarn : Res<YarnManager>,
+               definitions : Res<Assets<MoodAsset>>,
+               mut mood : ResMut<Mood>) {
+    let Some(definition) = definitions.get(&mood.definition) else { return; };
+    let target = definition.sample(definition.tension(&yarn));
+    let t = 1. - (-time.delta_seconds() * 3. / MOOD_FADE_TIME).exp();
+    mood.current = mood.current.lerp(&target, t);
+}
+
+// Closer fog when the conversation gets tense
+fn fog_update(mood : Res<Mood>, mut fog : Query<(&mut FogSettings, &CamId)>) {
+    for (mut fog, _) in fog.iter_mut().filter(|(_, id)| **id == CamId::Player) {
+        fog.falloff = FogFalloff::Linear { start : (mood.current.fog - FOG_DEPTH).max(0.), end : mood.current.fog };
+    }
+}
+
+// Darken the borders of the screen, only while the game is shown
+fn vignette_update(mood : Res<Mood>,
+                   cameras : Query<(&Camera, &CamId)>,
+                   mut vignette : Query<&mut BackgroundColor, With<Vignette>>) {
+    let playing = cameras.iter().any(|(c, id)| *id == CamId::Player && c.is_active);
+    for mut color in vignette.iter_mut() {
+        color.0.set_a(if playing { mood.current.vignette.clamp(0., 1.) } else { 0. });
+    }
+}
+
+// Subtle shake of the player camera, turned off with reduced motion
+fn shake_update(time : Res<Time>,
+                mood : Res<Mood>,
+                settings : Res<Settings>,
+                perlin : Res<PerlinNoise>,
+                mut cameras : Query<(&mut Transform, &CamId)>) {
+    let amount = if settings.reduced_motion { 0. } else { mood.current.shake };
+    let t = time.elapsed_seconds_f64() * SHAKE_SPEED;
+    let offset = Vec3::new(perlin.0.get([t, 0.5]) as f32, perlin.0.get([0.5, t]) as f32, 0.) * amount;
+    for (mut trans, _) in cameras.iter_mut().filter(|(_, id)| **id == CamId::Player) {
+        trans.translation = offset;
+    }
+}
@@ -0,0 +1,486 @@
+// Deterministic recording and replay of playthroughs
+// The log stores every continue and card choice with its timing, keyed by the yarn line id,
+// so a bug report can be reproduced in game or headless against the current script
+// The header stores the topics hidden in the content settings,
+// and in New Game+ runs what the script remembered from previous runs
+
+use super::{StoryState, settings::Settings, content::hidden_topic, dialogue::{DialogueState, HiddenLine, RunMemory, option_key, parse_line, reset_run, story_function}, yarn::*};
+use bevy::prelude::*;
+use yarn_spinner::{LineHandler, YarnProgram, YarnRunner, YarnStorage};
+use std::{fs, iter::Peekable, slice::Iter, collections::HashSet};
+
+// ---
+// Constants
+
+const REPLAY_HEADER : &str = "# strawbevy-jam replay v1";
+
+// ---
+// Resources
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplayEvent {
+    Continue { time : f32, line : String },
+    Select { time : f32, line : String, card : String },
+}
+
+impl ReplayEvent {
+    fn time(&self) -> f32 {
+        match self {
+            ReplayEvent::Continue { time, .. } => *time,
+            ReplayEvent::Select { time, .. } => *time,
+        }
+    }
+
+    // One event per line, tab separated since both line ids and cards have spaces
+    fn to_line(&self) -> String {
+        match self {
+            ReplayEvent::Continue { time, line } => format!("c\t{:.3}\t{}", time, line),
+            ReplayEvent::Select { time, line, card } => format!("s\t{:.3}\t{}\t{}", time, line, card),
+        }
+    }
+
+    fn from_line(l : &str) -> Option<ReplayEvent> {
+        let l : Vec<&str> = l.split('\t').collect();
+        let time = l.get(1)?.parse::<f32>().ok()?;
+        match (l[0], l.len()) {
+            ("c", 3) => Some(ReplayEvent::Continue { time, line : l[2].to_string() }),
+            ("s", 4) => Some(ReplayEvent::Select { time, line : l[2].to_string(), card : l[3].to_string() }),
+            _ => None
+        }
+    }
+}
+
+// What a run depends on besides the choices, written as comments at the start of the log
+// "# hidden" lines have the hidden topics, and "# new_game_plus" starts what the script remembered,
+// followed by "# ended" and "# chose" lines with the endings and cards (all tab separated)
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct ReplayHeader {
+    pub hidden_topics : HashSet<String>,
+    pub memory : Option<RunMemory>,
+}
+
+impl ReplayHeader {
+    // Read it from a log or golden choices
+    pub fn read<'a>(lines : impl Iterator<Item = &'a str>) -> ReplayHeader {
+        let mut header = ReplayHeader::default();
+        for l in lines.filter_map(|l| l.strip_prefix("# ")) {
+            let l : Vec<&str> = l.split('\t').collect();
+            match (l[0], l.get(1), &mut header.memory) {
+                ("hidden", Some(topic), _) => { header.hidden_topics.insert(topic.to_string()); },
+                ("new_game_plus", None, m) => { m.get_or_insert_with(RunMemory::default); },
+                ("ended", Some(id), Some(m)) => { m.endings.insert(id.to_string()); },
+                ("chose", Some(card), Some(m)) => { m.chosen.insert(card.to_string()); },
+                _ => ()
+            }
+        }
+        header
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let sorted = |set : &HashSet<String>| {
+            let mut v : Vec<String> = set.iter().cloned().collect();
+            v.sort();
+            v
+        };
+        let mut lines : Vec<String> = sorted(&self.hidden_topics).iter().map(|t| format!("# hidden\t{}", t)).collect();
+        if let Some(memory) = &self.memory {
+            lines.push("# new_game_plus".to_string());
+            lines.extend(sorted(&memory.endings).iter().map(|e| format!("# ended\t{}", e)));
+            lines.extend(sorted(&memory.chosen).iter().map(|c| format!("# chose\t{}", c)));
+        }
+        lines
+    }
+}
+
+#[derive(Default, PartialEq)]
+pub enum ReplayMode {
+    #[default]
+    Off,
+    Recording,
+    Replaying,
+}
+
+#[derive(Resource, Default)]
+pub struct Replay {
+    pub mode : ReplayMode,
+    pub headless : bool,
+    path : Option<String>,
+    events : Vec<ReplayEvent>,
+    header : ReplayHeader,
+    index : usize,
+    timer : f32,
+    line : String,
+}
+
+impl Replay {
+    // Read the replay options from the command line
+    // --record <file> saves a new log, --replay <file> plays one back (add --headless to skip rendering)
+    pub fn from_args(mut args : impl Iterator<Item = String>) -> Replay {
+        let mut replay = Replay::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => {
+                    replay.mode = ReplayMode::Recording;
+                    replay.path = args.next();
+                    if replay.path.is_none() { println!("Warning, missing file to record the replay"); }
+                },
+                "--replay" => {
+                    let Some(path) = args.next() else { println!("Warning, missing replay file"); continue; };
+                    if let Err(e) = replay.load(&path) {
+                        println!("Warning, can't load replay {}: {}", path, e);
+                    }
+                },
+                "--headless" => replay.headless = true,
+                _ => ()
+            }
+        }
+        replay
+    }
+
+    fn load(&mut self, path : &str) -> Result<(), String> {
+        let data = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        self.events = read_events(&data)?;
+        self.header = ReplayHeader::read(data.lines());
+        self.mode = ReplayMode::Replaying;
+        Ok(())
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else { return; };
+        let mut data = vec![REPLAY_HEADER.to_string()];
+        data.extend(self.header.lines());
+        data.extend(self.events.iter().map(|e| e.to_line()));
+        if fs::write(path, data.join("\n") + "\n").is_err() {
+            println!("Warning, problem saving replay {}", path);
+        }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.mode == ReplayMode::Replaying
+    }
+
+    // A new run starts, the recording starts again with what the script remembers in this run
+    pub fn start_run(&mut self, memory : Option<RunMemory>) {
+        if self.mode == ReplayMode::Recording {
+            self.events.clear();
+            self.header.memory = memory;
+            self.timer = 0.;
+            self.save();
+        }
+    }
+
+    // What the script remembers, the one from the log while replaying so it plays like it was recorded
+    pub fn memory(&self, story : &StoryState) -> Option<RunMemory> {
+        if self.is_replaying() { self.header.memory.clone() } else { story.memory() }
+    }
+
+    // Topics hidden in this run, the ones from the log while replaying
+    // While recording the header follows the settings, so a replay hides the same topics
+    pub fn hidden_topics(&mut self, settings : &Settings) -> HashSet<String> {
+        if self.mode == ReplayMode::Recording && self.header.hidden_topics != settings.hidden_topics {
+            self.header.hidden_topics = settings.hidden_topics.clone();
+            self.save();
+        }
+        if self.is_replaying() { self.header.hidden_topics.clone() } else { settings.hidden_topics.clone() }
+    }
+
+    // Time since the last event, only counted while playing
+    pub fn tick(&mut self, delta : f32) {
+        if self.mode != ReplayMode::Off {
+            self.timer += delta;
+        }
+    }
+
+    // The line that is currently being shown
+    pub fn line(&mut self, id : &str) {
+        self.line = id.to_string();
+    }
+
+    // The card that the replay is going to play next
+    pub fn next_card(&self) -> Option<&str> {
+        match self.events.get(self.index) {
+            Some(ReplayEvent::Select { card, .. }) if self.is_replaying() => Some(card),
+            _ => None
+        }
+    }
+
+    fn push(&mut self, event : ReplayEvent) {
+        self.events.push(event);
+        self.timer = 0.;
+        self.save();
+    }
+
+    fn advance(&mut self) {
+        if let Some(event) = self.events.get(self.index) {
+            let line = match event {
+                ReplayEvent::Continue { line, .. } => line,
+                ReplayEvent::Select { line, .. } => line,
+            };
+            if *line != self.line {
+                println!("Warning, replay desynced at event {} (expected {}, found {})", self.index, line, self.line);
+            }
+        }
+
+        self.index += 1;
+        self.timer = 0.;
+        if self.index >= self.events.len() {
+            println!("Replay finished");
+            self.mode = ReplayMode::Off;
+        }
+    }
+
+    // Check if the dialogue should continue, recording the input or replacing it with the log
+    pub fn continued(&mut self, pressed : bool) -> bool {
+        match self.mode {
+            ReplayMode::Off => pressed,
+            ReplayMode::Recording => {
+                if pressed {
+                    self.push(ReplayEvent::Continue { time : self.timer, line : self.line.clone() });
+                }
+                pressed
+            },
+            ReplayMode::Replaying => match self.events.get(self.index) {
+                Some(e @ ReplayEvent::Continue { .. }) if self.timer >= e.time() => {
+                    self.advance();
+                    true
+                },
+                _ => false
+            }
+        }
+    }
+
+    // Check if the line being revealed should be shown whole, while replaying when the next continue is due
+    // It is never recorded, only the continues that go on with the dialogue are
+    pub fn skipped(&self, pressed : bool) -> bool {
+        match self.mode {
+            ReplayMode::Replaying => matches!(self.events.get(self.index), Some(e @ ReplayEvent::Continue { .. }) if self.timer >= e.time()),
+            _ => pressed
+        }
+    }
+
+    // Check if the selected card should be played
+    pub fn confirmed(&self, pressed : bool) -> bool {
+        match self.mode {
+            ReplayMode::Replaying => matches!(self.events.get(self.index), Some(e @ ReplayEvent::Select { .. }) if self.timer >= e.time()),
+            _ => pressed
+        }
+    }
+
+    // A card was played
+    pub fn record_select(&mut self, card : &str) {
+        match self.mode {
+            ReplayMode::Off => (),
+            ReplayMode::Recording => self.push(ReplayEvent::Select { time : self.timer, line : self.line.clone(), card : card.to_string() }),
+            ReplayMode::Replaying => self.advance(),
+        }
+    }
+}
+
+// ---
+// Headless
+
+// Script state without rendering, following the same rules as the game
+pub struct Playthrough {
+    pub runner : YarnRunner,
+    pub lines : LineHandler,
+    pub storage : YarnStorage,
+    pub state : DialogueState,
+    pub transcript : Vec<String>,
+    pub ending : Option<String>,
+    // Hidden topics and what the script remembers from previous runs
+    pub header : ReplayHeader,
+    tags : YarnMetadataAsset,
+    line : String,
+}
+
+impl Playthrough {
+    // Load the compiled dialogue directly from the build folder
+    pub fn load(name : &str) -> Result<Playthrough, String> {
+        let bytes = fs::read(format!("assets/dialogue/build/{}.yarnc", name)).map_err(|e| e.to_string())?;
+        let lines = fs::read_to_string(format!("assets/dialogue/build/{}.yarnl", name)).map_err(|e| e.to_string())?;
+
+        let program = YarnProgram::new(&bytes).map_err(|_| "Error parsing the yarn program".to_string())?;
+        let mut runner = YarnRunner::new(program);
+        runner.set_node("Start").map_err(|_| "Error setting the start node".to_string())?;
+
+        Ok(Playthrough {
+            runner,
+            lines : LineHandler::new(&lines),
+            storage : YarnStorage::new(),
+            state : DialogueState::default(),
+            transcript : vec![],
+            ending : None,
+            header : ReplayHeader::default(),
+            tags : YarnMetadataAsset::from_program(&bytes),
+            line : String::new(),
+        })
+    }
+
+    // Start again from the beginning, like restarting the game
+    pub fn reset(&mut self) -> Result<(), String> {
+        if !reset_run(&mut self.runner, &mut self.storage, &mut self.state) {
+            return Err("Error setting the start node".to_string());
+        }
+        self.transcript.clear();
+        self.ending = None;
+        self.line.clear();
+        Ok(())
+    }
+
+    // Run the script until it ends, using the events to answer the questions
+    pub fn run(&mut self, events : &[ReplayEvent]) -> Result<(), String> {
+        let mut events = events.iter().peekable();
+
+        while self.ending.is_none() {
+            let dialogue = match self.runner.execute(&mut self.storage) {
+                Ok(Some(d)) => d,
+                Ok(None) => break,
+                Err(_) => return Err(format!("Error executing the script after {}", self.line)),
+            };
+
+            match dialogue {
+                ExecutionOutput::Line(line) => {
+                    self.line = line.id.clone();
+                    let (speaker, text, is_question) = parse_line(self.lines.line(&line).expect("Failed to parse yarn line"));
+
+                    // Hidden topics are softened or skipped like in the game, with the notice in the transcript
+                    let node = line_node(&line.id).unwrap_or_default();
+                    let topic = hidden_topic(self.tags.node_tags(node), &self.header.hidden_topics);
+                    match self.state.hide_line(&mut self.runner, node, topic.is_some(), is_question) {
+                        HiddenLine::Shown => (),
+                        HiddenLine::Softened => {
+                            self.transcript.push(format!("(Softened, {})", topic.unwrap_or_default()));
+                            self.use_continues(&mut events)?;
+                            continue;
+                        },
+                        HiddenLine::Summary => {
+                            self.transcript.push(format!("(Skipped, {})", topic.unwrap_or_default()));
+                            self.use_continues(&mut events)?;
+                            continue;
+                        },
+                        HiddenLine::Skipped => continue
+                    }
+
+                    self.transcript.push(match speaker {
+                        Some(s) => format!("{}:{}", s, text),
+                        None => text
+                    });
+
+                    // Continues are implied, but if they are recorded they must match
+                    if !is_question {
+                        self.use_continues(&mut events)?;
+                    }
+                },
+                ExecutionOutput::Options(opts) => {
+                    let options : Vec<String> = opts.iter()
+                        .map(|opt| self.lines.line(opt.line()).expect("Failed to parse yarn option"))
+                        .collect();
+                    let important = self.state.add_options(&options, &[], self.header.memory.is_some());
+
+                    let card = match skip_continues(&mut events) {
+                        Some(ReplayEvent::Select { line, card, .. }) => {
+                            self.check_line(line)?;
+                            card
+                        },
+                        _ => return Err(format!("Missing card choice for {}", self.line))
+                    };
+
+                    let opt = self.state.play_card(card)
+                        .ok_or_else(|| format!("The card {} is not in the hand", card))?;
+                    self.runner.select_option(opt).map_err(|_| format!("Invalid option {} for card {}", opt, card))?;
+                    self.transcript.push(format!("> {}", card));
+
+                    if important {
+                        self.state.play_important();
+                    }
+                },
+                ExecutionOutput::Command(c) => {
+                    self.transcript.push(format!("<<{}>>", c));
+                    let c : Vec<&str> = c.split(' ').collect();
+                    match c[0] {
+                        "discard" => { self.state.discard(c.get(1) == Some(&"keep")); },
+                        "give" => self.state.give(&c[1..].join(" ")),
+                        "take" => { self.state.take(&option_key(&c[1..].join(" "))); },
+                        "theEnd" => self.ending = Some(c.get(1).ok_or("Error parsing theEnd")?.to_string()),
+                        _ => ()
+                    }
+                },
+                ExecutionOutput::Function(function) => {
+                    let output = match yarn_spinner::handle_default_functions(&function) {
+                        Some(output) => output.unwrap(),
+                        None => story_function(&function.name, &function.args, self.header.memory.as_ref()).ok_or_else(|| format!("Unknown function {}", function.name))?
+                    };
+                    self.runner.return_function(output).unwrap();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Use the recorded continues of the current line
+    // The game can record more than one for the same line, like the one for the notice of a new act after it
+    fn use_continues(&self, events : &mut Peekable<Iter<ReplayEvent>>) -> Result<(), String> {
+        if let Some(ReplayEvent::Continue { line, .. }) = events.peek() {
+            self.check_line(line)?;
+        }
+        while let Some(ReplayEvent::Continue { line, .. }) = events.peek() {
+            if !line.is_empty() && *line != self.line {
+                break;
+            }
+            events.next();
+        }
+        Ok(())
+    }
+
+    // Events without a line (like golden choices) are not checked
+    fn check_line(&self, line : &str) -> Result<(), String> {
+        if !line.is_empty() && line != self.line {
+            return Err(format!("Replay desynced, expected {} but the script is at {}", line, self.line));
+        }
+        Ok(())
+    }
+}
+
+// Events of a log, skipping the header and comments
+pub fn read_events(data : &str) -> Result<Vec<ReplayEvent>, String> {
+    data.lines()
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| ReplayEvent::from_line(l).ok_or_else(|| format!("invalid event {}", l)))
+        .collect()
+}
+
+// Continues recorded before a choice (for example after a wait) are not needed headless
+fn skip_continues<'a>(events : &mut Peekable<Iter<'a, ReplayEvent>>) -> Option<&'a ReplayEvent> {
+    while let Some(ReplayEvent::Continue { .. }) = events.peek() {
+        events.next();
+    }
+    events.next()
+}
+
+// Run a replay without rendering against the current script
+// Returns the exit code, which is not zero if the replay doesn't match the script
+pub fn run_headless(replay : &Replay) -> i32 {
+    let mut play = match Playthrough::load("dialogue") {
+        Ok(p) => p,
+        Err(e) => { println!("Error loading the dialogue: {}", e); return 1; }
+    };
+    play.header = replay.header.clone();
+
+    let result = play.run(&replay.events);
+    play.transcript.iter().for_each(|l| println!("{}", l));
+
+    match result {
+        Err(e) => {
+            println!("Error: {}", e);
+            1
+        },
+        Ok(()) => {
+            match play.ending {
+                Some(n) => println!("Reached ending {}", n),
+                None => println!("The script finished without an ending"),
+            }
+            0
+        }
+    }
+}
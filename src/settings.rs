@@ -0,0 +1,400 @@
+// Player preferences, saved in the persistent storage and applied on startup
+
+use super::{GameState, Props, PersistentStorage, accessibility, actions::{Actions, Action, InputMap, Rebinding}, menu::{MenuButton, MenuReturn, spawn_button, text_style, MENU_BUTTON_REGULAR, MENU_BUTTON_HOVER}};
+use bevy::{prelude::*, window::{PrimaryWindow, WindowMode}, a11y::accesskit::Role};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+// ---
+// Constants
+
+const SETTINGS_BACKGROUND : Color = Color::rgba(0.05, 0.12, 0.08, 0.95);
+
+// Characters per second, 0 shows the whole line at once
+const TEXT_SPEEDS : [(f32, &str); 4] = [(20., "Slow"), (40., "Normal"), (80., "Fast"), (0., "Instant")];
+const FONT_SIZES : [f32; 5] = [16., 20., 24., 28., 32.];
+const RESOLUTIONS : [(f32, f32); 5] = [(800., 800.), (1024., 1024.), (1280., 720.), (1600., 900.), (1920., 1080.)];
+const CONTROLS : [Action; 6] = [Action::Left, Action::Right, Action::Confirm, Action::Cancel, Action::Continue, Action::Menu];
+const WINDOW_MODES : [(WindowMode, &str); 3] = [
+    (WindowMode::Windowed, "Windowed"),
+    (WindowMode::BorderlessFullscreen, "Borderless"),
+    (WindowMode::Fullscreen, "Fullscreen"),
+];
+
+// ---
+// Resources
+
+#[derive(Resource, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    pub text_speed : f32,
+    pub font_size : f32,
+    pub master_volume : f32,
+    pub music_volume : f32,
+    pub sfx_volume : f32,
+    pub window_mode : WindowMode,
+    pub resolution : (f32, f32),
+    pub reduced_motion : bool,
+    // Read the dialogue and the cards aloud, missing in older saves
+    #[serde(default)]
+    pub text_to_speech : bool,
+    // Content warning topics that are softened or skipped
+    #[serde(default)]
+    pub hidden_topics : HashSet<String>,
+    // Show the mood meters while playing
+    #[serde(default)]
+    pub mood_hud : bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            text_speed : TEXT_SPEEDS[1].0,
+            font_size : 24.,
+            master_volume : 1.,
+            music_volume : 0.8,
+            sfx_volume : 0.8,
+            window_mode : WindowMode::Windowed,
+            resolution : RESOLUTIONS[0],
+            reduced_motion : false,
+            text_to_speech : false,
+            hidden_topics : HashSet::new(),
+            mood_hud : false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SettingKind {
+    TextSpeed,
+    FontSize,
+    MasterVolume,
+    MusicVolume,
+    SfxVolume,
+    WindowMode,
+    Resolution,
+    ReducedMotion,
+    TextToSpeech,
+    MoodHud,
+}
+
+// Move to the previous or next value of a list
+fn step<T : PartialEq + Copy>(list : &[T], current : T, dir : i32) -> T {
+    let i = list.iter().position(|x| *x == current).unwrap_or(0) as i32;
+    list[(i + dir).clamp(0, list.len() as i32 - 1) as usize]
+}
+
+impl Settings {
+    // Saved settings, or the defaults if there are none
+    pub fn load(storage : &PersistentStorage) -> Settings {
+        storage.0.get::<Settings>("settings").unwrap_or_default()
+    }
+
+    // Effective volume of the music and sound effects
+    pub fn music(&self) -> f32 { self.master_volume * self.music_volume }
+    pub fn sfx(&self) -> f32 { self.master_volume * self.sfx_volume }
+
+    pub fn change(&mut self, kind : SettingKind, dir : i32) {
+        let volume = |v : f32| (v + dir as f32 * 0.1).clamp(0., 1.);
+        match kind {
+            SettingKind::TextSpeed => {
+                let speeds : Vec<f32> = TEXT_SPEEDS.iter().map(|(s, _)| *s).collect();
+                self.text_speed = step(&speeds, self.text_speed, dir);
+            },
+            SettingKind::FontSize => self.font_size = step(&FONT_SIZES, self.font_size, dir),
+            SettingKind::MasterVolume => self.master_volume = volume(self.master_volume),
+            SettingKind::MusicVolume => self.music_volume = volume(self.music_volume),
+            SettingKind::SfxVolume => self.sfx_volume = volume(self.sfx_volume),
+            SettingKind::WindowMode => {
+                let modes : Vec<WindowMode> = WINDOW_MODES.iter().map(|(m, _)| *m).collect();
+                self.window_mode = step(&modes, self.window_mode, dir);
+            },
+            SettingKind::Resolution => self.resolution = step(&RESOLUTIONS, self.resolution, dir),
+            SettingKind::ReducedMotion => self.reduced_motion = !self.reduced_motion,
+            SettingKind::TextToSpeech => self.text_to_speech = !self.text_to_speech,
+            SettingKind::MoodHud => self.mood_hud = !self.mood_hud,
+        }
+    }
+
+    pub fn display(&self, kind : SettingKind) -> String {
+        match kind {
+            SettingKind::TextSpeed => TEXT_SPEEDS.iter().find(|(s, _)| *s == self.text_speed).map_or("Custom", |(_, n)| n).to_string(),
+            SettingKind::FontSize => format!("{}", self.font_size),
+            SettingKind::MasterVolume => format!("{:.0}%", self.master_volume * 100.),
+            SettingKind::MusicVolume => format!("{:.0}%", self.music_volume * 100.),
+            SettingKind::SfxVolume => format!("{:.0}%", self.sfx_volume * 100.),
+            SettingKind::WindowMode => WINDOW_MODES.iter().find(|(m, _)| *m == self.window_mode).map_or("Custom", |(_, n)| n).to_string(),
+            SettingKind::Resolution => format!("{}x{}", self.resolution.0, self.resolution.1),
+            SettingKind::ReducedMotion => (if self.reduced_motion { "On" } else { "Off" }).to_string(),
+            SettingKind::TextToSpeech => (if self.text_to_speech { "On" } else { "Off" }).to_string(),
+            SettingKind::MoodHud => (if self.mood_hud { "On" } else { "Off" }).to_string(),
+        }
+    }
+}
+
+// ---
+// Components
+
+#[derive(Component)]
+pub struct SettingsNode;
+
+#[derive(Component)]
+pub struct SettingButton(SettingKind, i32);
+
+#[derive(Component)]
+pub struct SettingValue(SettingKind);
+
+#[derive(Component)]
+pub enum ControlButton {
+    Rebind(Action),
+    Reset,
+}
+
+#[derive(Component)]
+pub struct BindingValue(Action);
+
+// ---
+// Startup systems
+
+// Settings screen, reachable from the menu and the pause overlay
+pub fn settings_init(mut cmd : Commands, props : Res<Props>, settings : Res<Settings>, map : Res<InputMap>) {
+    let rows = [
+        (SettingKind::TextSpeed, "Text speed"),
+        (SettingKind::FontSize, "Font size"),
+        (SettingKind::MasterVolume, "Volume"),
+        (SettingKind::MusicVolume, "Music"),
+        (SettingKind::SfxVolume, "Effects"),
+        (SettingKind::WindowMode, "Window"),
+        (SettingKind::Resolution, "Resolution"),
+        (SettingKind::ReducedMotion, "Reduced motion"),
+        (SettingKind::TextToSpeech, "Text to speech"),
+        (SettingKind::MoodHud, "Mood meters"),
+    ];
+
+    let arrow_style = Style {
+        size : Size::new(Val::Px(32.0), Val::Px(32.0)),
+        justify_content : JustifyContent::Center,
+        align_items : AlignItems::Center,
+        ..default()
+    };
+
+    cmd.spawn((
+        NodeBundle {
+            style : Style {
+                size : Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                align_items : AlignItems::Center,
+                justify_content : JustifyContent::Center,
+                flex_direction: FlexDirection::Column,
+                gap : Size::new(Val::Auto, Val::Px(12.0)),
+                ..default()
+            },
+            background_color : SETTINGS_BACKGROUND.into(),
+            visibility : Visibility::Hidden,
+            ..default()
+        },
+        SettingsNode
+    ))
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section("Settings", text_style(&props, 48.0)));
+
+        for (kind, label) in rows {
+            parent.spawn(NodeBundle {
+                style : Style {
+                    align_items : AlignItems::Center,
+                    gap : Size::new(Val::Px(8.0), Val::Auto),
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|row| {
+                row.spawn(TextBundle::from_section(label, text_style(&props, 20.0)).with_style(Style {
+                    size : Size::width(Val::Px(220.0)),
+                    ..default()
+                }));
+                for (dir, arrow, name) in [(-1, "<", "lower"), (1, ">", "higher")] {
+                    if dir == 1 {
+                        row.spawn((
+                            TextBundle::from_section(settings.display(kind), text_style(&props, 20.0)).with_style(Style {
+                                size : Size::width(Val::Px(150.0)),
+                                ..default()
+                            }).with_text_alignment(TextAlignment::Center),
+                            SettingValue(kind)
+                        ));
+                    }
+                    row.spawn((
+                        ButtonBundle {
+                            style : arrow_style.clone(),
+                            background_color : MENU_BUTTON_REGULAR.into(),
+                            ..default()
+                        },
+                        SettingButton(kind, dir),
+                        accessibility::label(Role::Button, &format!("{} {}", label, name))
+                    )).with_children(|button| {
+                        button.spawn(TextBundle::from_section(arrow, text_style(&props, 20.0)));
+                    });
+                }
+            });
+        }
+
+        // Controls, two actions per row, clicking one waits for the new key or button
+        parent.spawn(NodeBundle {
+            style : Style {
+                align_items : AlignItems::Center,
+                gap : Size::new(Val::Px(8.0), Val::Auto),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn(TextBundle::from_section("Controls", text_style(&props, 20.0)).with_style(Style {
+                size : Size::width(Val::Px(220.0)),
+                ..default()
+            }));
+            row.spawn((
+                ButtonBundle {
+                    style : Style { size : Size::new(Val::Px(96.0), Val::Px(32.0)), ..arrow_style.clone() },
+                    background_color : MENU_BUTTON_REGULAR.into(),
+                    ..default()
+                },
+                ControlButton::Reset,
+                accessibility::label(Role::Button, "Reset controls")
+            )).with_children(|button| {
+                button.spawn(TextBundle::from_section("Reset", text_style(&props, 20.0)));
+            });
+        });
+
+        parent.spawn(NodeBundle {
+            style : Style {
+                size : Size::width(Val::Px(740.0)),
+                flex_wrap : FlexWrap::Wrap,
+                justify_content : JustifyContent::Center,
+                gap : Size::new(Val::Px(16.0), Val::Px(8.0)),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|grid| {
+            for action in CONTROLS {
+                grid.spawn(NodeBundle {
+                    style : Style {
+                        align_items : AlignItems::Center,
+                        gap : Size::new(Val::Px(8.0), Val::Auto),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|row| {
+                    row.spawn(TextBundle::from_section(format!("{:?}", action), text_style(&props, 20.0)).with_style(Style {
+                        size : Size::width(Val::Px(110.0)),
+                        ..default()
+                    }));
+                    row.spawn((
+                        ButtonBundle {
+                            style : Style {
+                                size : Size::new(Val::Px(220.0), Val::Px(32.0)),
+                                justify_content : JustifyContent::Center,
+                                align_items : AlignItems::Center,
+                                ..default()
+                            },
+                            background_color : MENU_BUTTON_REGULAR.into(),
+                            ..default()
+                        },
+                        ControlButton::Rebind(action),
+                        accessibility::label(Role::Button, &format!("Rebind {:?}", action))
+                    )).with_children(|button| {
+                        button.spawn((TextBundle::from_section(map.describe(action), text_style(&props, 16.0)), BindingValue(action)));
+                    });
+                });
+            }
+        });
+
+        spawn_button(parent, "Back", text_style(&props, 24.0), MenuButton::Back);
+    });
+}
+
+// ---
+// State transitions
+
+pub fn enter_settings(mut node : Query<&mut Visibility, With<SettingsNode>>) {
+    node.iter_mut().for_each(|mut x| *x = Visibility::Visible);
+}
+
+pub fn exit_settings(mut node : Query<&mut Visibility, With<SettingsNode>>, mut rebinding : ResMut<Rebinding>) {
+    node.iter_mut().for_each(|mut x| *x = Visibility::Hidden);
+    rebinding.0 = None;
+}
+
+// ---
+// Update systems
+
+// Change the settings with the arrow buttons and rebind the controls
+pub fn settings_update(mut settings : ResMut<Settings>,
+                       mut state : ResMut<NextState<GameState>>,
+                       mut map : ResMut<InputMap>,
+                       mut rebinding : ResMut<Rebinding>,
+                       menu_return : Res<MenuReturn>,
+                       actions : Res<Actions>,
+                       mut buttons : Query<(&Interaction, &mut BackgroundColor, &SettingButton), Changed<Interaction>>,
+                       mut controls : Query<(&Interaction, &mut BackgroundColor, &ControlButton), (Changed<Interaction>, Without<SettingButton>)>,
+                       mut bindings : Query<(&mut Text, &BindingValue)>) {
+    // The menu key could be the one being bound
+    if actions.just_pressed(Action::Menu) && rebinding.0.is_none() && !rebinding.is_changed() {
+        state.set(menu_return.0.clone());
+    }
+
+    for (inter, mut bg, SettingButton(kind, dir)) in buttons.iter_mut() {
+        match *inter {
+            Interaction::Clicked => settings.change(*kind, *dir),
+            Interaction::Hovered => *bg = MENU_BUTTON_HOVER.into(),
+            Interaction::None => *bg = MENU_BUTTON_REGULAR.into(),
+        }
+    }
+
+    for (inter, mut bg, button) in controls.iter_mut() {
+        match (*inter, button) {
+            (Interaction::Clicked, ControlButton::Rebind(action)) => rebinding.0 = Some(*action),
+            (Interaction::Clicked, ControlButton::Reset) => {
+                *map = InputMap::defaults();
+                rebinding.0 = None;
+            },
+            (Interaction::Hovered, _) => *bg = MENU_BUTTON_HOVER.into(),
+            (Interaction::None, _) => *bg = MENU_BUTTON_REGULAR.into(),
+        }
+    }
+
+    if map.is_changed() || rebinding.is_changed() {
+        for (mut text, BindingValue(action)) in bindings.iter_mut() {
+            text.sections[0].value = if rebinding.0 == Some(*action) { "Press a key...".to_string() } else { map.describe(*action) };
+        }
+    }
+}
+
+// Apply and save the settings when they change
+pub fn apply_settings(settings : Res<Settings>,
+                      mut storage : ResMut<PersistentStorage>,
+                      mut props : ResMut<Props>,
+                      mut window : Query<&mut Window, With<PrimaryWindow>>,
+                      mut values : Query<(&mut Text, &SettingValue)>,
+                      mut applied : Local<Option<(WindowMode, (f32, f32))>>) {
+    // Only touch the window when its own settings change, so other settings don't undo a manual resize
+    let window_settings = (settings.window_mode, settings.resolution);
+    if *applied != Some(window_settings) {
+        if let Ok(mut window) = window.get_single_mut() {
+            window.mode = settings.window_mode;
+            window.resolution.set(settings.resolution.0, settings.resolution.1);
+            *applied = Some(window_settings);
+        }
+    }
+
+    // The dialogue box picks up the new size the next time it is drawn
+    for style in props.box_style.values_mut() {
+        style.font_size = settings.font_size;
+    }
+
+    for (mut text, SettingValue(kind)) in values.iter_mut() {
+        text.sections[0].value = settings.display(*kind);
+    }
+
+    if storage.0.set("settings", &*settings).is_err() {
+        println!("Warning, problem saving the settings");
+    }
+}
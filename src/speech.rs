@@ -0,0 +1,142 @@
+// Text to speech for accessibility, the dialogue lines and the hovered cards are read aloud
+// The backend is chosen from the command line:
+// --tts <command> runs a program for each utterance (espeak by default), --tts-file <file> writes them to a file
+// and --tts-null only records them
+
+use super::{settings::Settings, dialogue::{DialogueState, DialogueText, DialogueCard}};
+use bevy::prelude::*;
+use std::{fs::OpenOptions, io::Write, process::{Child, Command}, sync::{Arc, Mutex}};
+
+// ---
+// Constants
+
+const DEFAULT_COMMAND : &str = "espeak";
+
+// ---
+// Backends
+
+pub trait SpeechBackend : Send + Sync {
+    fn speak(&mut self, text : &str);
+    // Interrupt the current utterance, if the backend can
+    fn stop(&mut self) {}
+}
+
+// Runs a command with the text as the last argument, like `espeak "Remie: hello"`
+pub struct CommandBackend {
+    program : String,
+    args : Vec<String>,
+    child : Option<Child>,
+}
+
+impl CommandBackend {
+    pub fn new(command : &str) -> CommandBackend {
+        let mut parts = command.split_whitespace().map(|s| s.to_string());
+        CommandBackend {
+            program : parts.next().unwrap_or_else(|| DEFAULT_COMMAND.to_string()),
+            args : parts.collect(),
+            child : None,
+        }
+    }
+}
+
+impl SpeechBackend for CommandBackend {
+    fn speak(&mut self, text : &str) {
+        self.stop();
+        match Command::new(&self.program).args(&self.args).arg(text).spawn() {
+            Ok(child) => self.child = Some(child),
+            Err(e) => println!("Warning, can't run the speech command {}: {}", self.program, e),
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            child.kill().ok();
+            child.wait().ok();
+        }
+    }
+}
+
+// Appends every utterance to a file, one per line
+pub struct FileBackend {
+    path : String,
+}
+
+impl SpeechBackend for FileBackend {
+    fn speak(&mut self, text : &str) {
+        let file = OpenOptions::new().create(true).append(true).open(&self.path);
+        if file.and_then(|mut f| writeln!(f, "{}", text)).is_err() {
+            println!("Warning, problem writing the speech to {}", self.path);
+        }
+    }
+}
+
+// Keeps the utterances in memory, the log can be shared to check what was said
+#[derive(Default)]
+pub struct RecordingBackend {
+    pub spoken : Arc<Mutex<Vec<String>>>,
+}
+
+impl SpeechBackend for RecordingBackend {
+    fn speak(&mut self, text : &str) {
+        self.spoken.lock().expect("Speech log poisoned").push(text.to_string());
+    }
+}
+
+// ---
+// Resources
+
+#[derive(Resource)]
+pub struct Speech(pub Box<dyn SpeechBackend>);
+
+impl Speech {
+    // Read the speech backend from the command line
+    pub fn from_args(args : &[String]) -> Speech {
+        let value = |flag : &str| args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).cloned();
+        if let Some(path) = value("--tts-file") {
+            return Speech(Box::new(FileBackend { path }));
+        }
+        if args.iter().any(|a| a == "--tts-null") {
+            return Speech(Box::<RecordingBackend>::default());
+        }
+        Speech(Box::new(CommandBackend::new(&value("--tts").unwrap_or_else(|| DEFAULT_COMMAND.to_string()))))
+    }
+
+    pub fn say(&mut self, text : &str) {
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !text.is_empty() {
+            self.0.speak(&text);
+        }
+    }
+}
+
+// ---
+// Update systems
+
+// Read the new dialogue lines and the card under the cursor
+pub fn speech_update(mut speech : ResMut<Speech>,
+                     settings : Res<Settings>,
+                     text : Res<DialogueText>,
+                     state : Res<DialogueState>,
+                     cards : Query<&DialogueCard>,
+                     mut last : Local<(String, Option<Entity>)>) {
+    if !settings.text_to_speech {
+        return;
+    }
+
+    if text.is_changed() && text.line() != last.0 {
+        last.0 = text.line().to_string();
+        match &text.voice {
+            Some(voice) => speech.say(&format!("{}: {}", voice, text.line())),
+            None => speech.say(text.line()),
+        }
+    }
+
+    if state.selected_card != last.1 {
+        last.1 = state.selected_card;
+        if let Some(card) = state.selected_card.and_then(|e| cards.get(e).ok()) {
+            if let Some(words) = state.card_text(card.id()) {
+                speech.say(&format!("Card: {}", words));
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+// Tooltip of the hovered card, with what happened the last time it was played for this question
+
+use super::{CamId, Props, StoryState, menu::text_style, dialogue::{DialogueState, DialogueCard}, yarn::YarnManager};
+use bevy::prelude::*;
+
+// ---
+// Constants
+
+const TOOLTIP_BACKGROUND : Color = Color::rgba(0.05, 0.12, 0.08, 0.9);
+
+// ---
+// Components
+
+#[derive(Component)]
+pub struct Tooltip;
+
+// ---
+// Startup systems
+
+pub fn tooltip_init(mut cmd : Commands, props : Res<Props>) {
+    cmd.spawn((
+        NodeBundle {
+            style : Style {
+                position_type : PositionType::Absolute,
+                position : UiRect { left : Val::Percent(25.0), bottom : Val::Percent(42.0), ..default() },
+                size : Size::width(Val::Percent(50.0)),
+                padding : UiRect::all(Val::Px(8.0)),
+                justify_content : JustifyContent::Center,
+                display : Display::None,
+                ..default()
+            },
+            background_color : TOOLTIP_BACKGROUND.into(),
+            ..default()
+        },
+        Tooltip
+    ))
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section("", text_style(&props, 16.0)));
+    });
+}
+
+// ---
+// Update systems
+
+// Show the tooltip while a card that was played before for this question is hovered
+pub fn tooltip_update(story : Res<StoryState>,
+                      state : Res<DialogueState>,
+                      yarn : Res<YarnManager>,
+                      time : Res<Time>,
+                      cards : Query<&DialogueCard>,
+                      cameras : Query<(&Camera, &CamId)>,
+                      mut tooltip : Query<(&mut Style, &Children), With<Tooltip>>,
+                      mut text : Query<&mut Text>) {
+    let Ok((mut style, children)) = tooltip.get_single_mut() else { return; };
+
+    let playing = cameras.iter().any(|(c, id)| *id == CamId::Player && c.is_active) && !time.is_paused();
+    let consequence = state.selected_card
+        .filter(|_| playing && yarn.waiting_response)
+        .and_then(|e| cards.get(e).ok())
+        .and_then(|card| story.consequences.get(&story.current_question)?.get(card.id()));
+
+    let display = if consequence.is_some() { Display::Flex } else { Display::None };
+    if style.display != display {
+        style.display = display;
+    }
+    if let (Some(consequence), Some(mut text)) = (consequence, children.first().and_then(|c| text.get_mut(*c).ok())) {
+        let value = format!("Last time: {}", consequence);
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
@@ -8,6 +8,7 @@ use bevy::{
 };
 use yarn_spinner::{LineHandler, YarnProgram, YarnRunner, YarnStorage};
 pub use yarn_spinner::ExecutionOutput;
+use std::collections::HashMap;
 
 // ---
 // Plugin
@@ -20,6 +21,7 @@ impl Plugin for YarnPlugin {
            .init_asset_loader::<YarnRunnerAssetLoader>()
            .add_asset::<YarnLinesAsset>()
            .init_asset_loader::<YarnLinesAssetLoader>()
+           .add_asset::<YarnMetadataAsset>()
            .insert_resource(YarnManager::new());
     }
 }
@@ -33,6 +35,7 @@ pub struct YarnManager {
     pub storage : YarnStorage,
     pub runner : Option<Handle<YarnRunnerAsset>>,
     pub lines : Option<Handle<YarnLinesAsset>>,
+    pub metadata : Option<Handle<YarnMetadataAsset>>,
     pub waiting_continue : bool,
     pub waiting_response : bool,
     pub important_decision : bool,
@@ -49,9 +52,18 @@ impl YarnManager {
         }
     }
 
+    // Forget the progress of the current run, the runner and storage are reset with dialogue::reset_run
+    pub fn reset(&mut self) {
+        self.waiting_continue = false;
+        self.waiting_response = false;
+        self.important_decision = false;
+        self.finished = false;
+    }
+
     pub fn load(&mut self, name : &str, assets : &Res<AssetServer>) {
         self.runner = Some(assets.load(format!("dialogue/build/{}.yarnc", name)));
         self.lines = Some(assets.load(format!("dialogue/build/{}.yarnl", name)));
+        self.metadata = Some(assets.load(format!("dialogue/build/{}.yarnc#tags", name)));
     }
 }
 
@@ -73,6 +85,7 @@ impl AssetLoader for YarnRunnerAssetLoader {
             let mut runner = YarnRunner::new(program.unwrap());
             runner.set_node("Start").unwrap();
             load_context.set_default_asset(LoadedAsset::new(YarnRunnerAsset(runner)));
+            load_context.set_labeled_asset("tags", LoadedAsset::new(YarnMetadataAsset::from_program(bytes)));
             Ok(())
         })
     }
@@ -97,9 +110,81 @@ impl AssetLoader for YarnLinesAssetLoader {
     fn extensions(&self) -> &[&str] { &["yarnl"] }
 }
 
+// Tags in the headers of the nodes (like cw:topic), loaded with the program as <name>.yarnc#tags
+#[derive(TypeUuid, Default)]
+#[uuid = "c41d7e2a-93b5-4f08-8a6e-1d2f5b7c9e60"]
+pub struct YarnMetadataAsset(pub HashMap<String, Vec<String>>);
+
+impl YarnMetadataAsset {
+    // The yarn_spinner crate doesn't expose the tags, so they are read from the protobuf of the compiled program
+    // The nodes are a map in the field 2 of the program (name in 1 and node in 2), and the tags are the field 4 of a node
+    pub fn from_program(bytes : &[u8]) -> YarnMetadataAsset {
+        let mut metadata = YarnMetadataAsset::default();
+        for (_, entry) in proto_fields(bytes).into_iter().filter(|(f, _)| *f == 2) {
+            let entry = proto_fields(entry);
+            let name = entry.iter().find(|(f, _)| *f == 1).and_then(|(_, n)| std::str::from_utf8(n).ok());
+            let node = entry.iter().find(|(f, _)| *f == 2);
+            let (Some(name), Some((_, node))) = (name, node) else { continue; };
+
+            let tags = proto_fields(node).into_iter()
+                .filter(|(f, _)| *f == 4)
+                .filter_map(|(_, t)| std::str::from_utf8(t).ok())
+                .map(|t| t.to_string())
+                .collect();
+            metadata.0.insert(name.to_string(), tags);
+        }
+        metadata
+    }
+
+    pub fn node_tags(&self, node : &str) -> &[String] {
+        self.0.get(node).map_or(&[], |t| t.as_slice())
+    }
+}
+
 // ---
 // Functions
 
+// Length delimited fields of a protobuf message with their number, the other types are skipped
+fn proto_fields(mut bytes : &[u8]) -> Vec<(u64, &[u8])> {
+    let mut fields = vec![];
+    while let Some(key) = proto_varint(&mut bytes) {
+        match key & 7 {
+            0 => { proto_varint(&mut bytes); },
+            1 => bytes = bytes.get(8..).unwrap_or_default(),
+            2 => {
+                let len = proto_varint(&mut bytes).unwrap_or(u64::MAX) as usize;
+                if len > bytes.len() {
+                    break;
+                }
+                let (value, rest) = bytes.split_at(len);
+                fields.push((key >> 3, value));
+                bytes = rest;
+            },
+            5 => bytes = bytes.get(4..).unwrap_or_default(),
+            _ => break
+        }
+    }
+    fields
+}
+
+fn proto_varint(bytes : &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&b, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+// Node of a line, the compiler names the lines as line:<file>-<node>-<number>
+pub fn line_node(id : &str) -> Option<&str> {
+    id.rsplit('-').nth(1)
+}
+
 // Return references to the runner and lines assets if they are loaded
 pub fn get_yarn_components<'a, 'b>(yarn : &'_ ResMut<YarnManager>,
                                    asset_runner : &'a mut ResMut<Assets<YarnRunnerAsset>>,